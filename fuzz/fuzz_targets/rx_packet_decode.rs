@@ -0,0 +1,44 @@
+//! Decodes arbitrary response packets against arbitrary requests. Decoding
+//! must always return either a value or an error, and never panic.
+//!
+//! The first bytes of the input choose the commands in the request, and the
+//! remainder is decoded as the response report.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate corsairlink;
+
+use corsairlink::devices::cooler::h110i::{Register, RegisterValue};
+use corsairlink::protocol::usbhid::{Command, RxPacket, TxPacket, FIRST_COMMAND_ID, PACKET_SIZE};
+
+/// The highest register address on the H110i
+const MAX_REGISTER: u8 = 0x1a;
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+
+    let command_count = (data[0] & 0x0f) as usize;
+    if data.len() < 1 + command_count {
+        return;
+    }
+
+    let mut commands: Vec<Command<Register, RegisterValue>> = Vec::new();
+    for b in &data[1..1 + command_count] {
+        let register = match Register::from_u8((b & 0x7f) % (MAX_REGISTER + 1)) {
+            Some(register) => register,
+            None => continue,
+        };
+        if b & 0x80 == 0 {
+            commands.push(Command::Read(register));
+        } else {
+            commands.push(Command::Write(register, RegisterValue::LedSelect(0)));
+        }
+    }
+
+    let response = &data[1 + command_count..];
+    let response = &response[..response.len().min(PACKET_SIZE)];
+    let _ = RxPacket::decode(TxPacket::new(FIRST_COMMAND_ID, commands), response);
+});
@@ -0,0 +1,125 @@
+//! # Asetek cooler simulator
+//!
+//! A software model of an Asetek-based cooler, answering every command with a
+//! status message just like the real hardware. The settings the host has
+//! written are kept so that tests can check them, since the cooler itself
+//! never reports them.
+//!
+//! ```
+//! use corsairlink::backends::asetek_simulator::AsetekSimulator;
+//! use corsairlink::devices::cooler::asetek;
+//!
+//! let mut cooler = asetek::Device::new(AsetekSimulator::new());
+//! let status = cooler.set_fan_duty(60).unwrap();
+//! assert_eq!(status.liquid_temperature, 31.4);
+//! assert_eq!(cooler.backend().fan_duty, Some(60));
+//! ```
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use errors::*;
+use backends::Backend;
+use protocol::asetek::{PumpMode, Request, CURVE_POINTS, STATUS_SIZE};
+
+#[derive(Debug)]
+pub struct AsetekSimulator {
+    pub color: [u8; 3],
+    pub alert_color: [u8; 3],
+    pub alert_temperature: u8,
+    /// The fan curve, as temperatures and duty cycles, if one has been set.
+    pub fan_curve: Option<([u8; CURVE_POINTS], [u8; CURVE_POINTS])>,
+    /// The fixed fan duty cycle, if one has been set. Setting a curve clears
+    /// it, and vice versa.
+    pub fan_duty: Option<u8>,
+    pub pump_mode: PumpMode,
+
+    /// In degrees Celsius, to one decimal place.
+    pub liquid_temperature: f64,
+    pub fan_rpm: u16,
+    pub pump_rpm: u16,
+    pub firmware_version: [u8; 4],
+
+    responses: VecDeque<[u8; STATUS_SIZE]>,
+}
+
+impl Default for AsetekSimulator {
+    fn default() -> AsetekSimulator {
+        AsetekSimulator::new()
+    }
+}
+
+impl AsetekSimulator {
+    /// Creates a simulator of an idle cooler running firmware 2.0.0.13.
+    pub fn new() -> AsetekSimulator {
+        AsetekSimulator {
+            color: [0xff, 0xff, 0xff],
+            alert_color: [0xff, 0x00, 0x00],
+            alert_temperature: 60,
+            fan_curve: None,
+            fan_duty: None,
+            pump_mode: PumpMode::Balanced,
+
+            liquid_temperature: 31.4,
+            fan_rpm: 960,
+            pump_rpm: 2040,
+            firmware_version: [2, 0, 0, 13],
+
+            responses: VecDeque::new(),
+        }
+    }
+
+    fn status(&self) -> [u8; STATUS_SIZE] {
+        let mut status = [0u8; STATUS_SIZE];
+        status[0] = (self.fan_rpm >> 8) as u8;
+        status[1] = self.fan_rpm as u8;
+        status[8] = (self.pump_rpm >> 8) as u8;
+        status[9] = self.pump_rpm as u8;
+        status[10] = self.liquid_temperature as u8;
+        status[14] = ((self.liquid_temperature * 10.0).round() as u32 % 10) as u8;
+        status[0x17..0x1b].copy_from_slice(&self.firmware_version);
+        status
+    }
+
+    fn execute(&mut self, request: Request) {
+        match request {
+            Request::Color { color, alert_color, alert_temperature } => {
+                self.color = color;
+                self.alert_color = alert_color;
+                self.alert_temperature = alert_temperature;
+            },
+            Request::FanCurve { temperatures, duties } => {
+                self.fan_curve = Some((temperatures, duties));
+                self.fan_duty = None;
+            },
+            Request::FixedFan(duty) => {
+                self.fan_duty = Some(duty);
+                self.fan_curve = None;
+            },
+            Request::Pump(mode) => self.pump_mode = mode,
+            Request::ReadStatus => (),
+        }
+    }
+}
+
+impl Backend for AsetekSimulator {
+    fn write_report(&mut self, data: &[u8], _timeout: Duration) -> Result<usize> {
+        let request = Request::decode(data)
+            .map_err(|e| Error::from(TransportError::Backend(format!("AsetekSimulator: {}", e))))?;
+        self.execute(request);
+        let status = self.status();
+        self.responses.push_back(status);
+        Ok(data.len())
+    }
+
+    fn read_report(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        match self.responses.pop_front() {
+            Some(response) => {
+                let len = buf.len().min(response.len());
+                buf[..len].copy_from_slice(&response[..len]);
+                Ok(len)
+            },
+            None => Err(TransportError::NoResponse.into()),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! # hidraw backend
+//!
+//! Talks to devices through the operating system's HID driver using hidapi.
+//! On Linux this goes through `/dev/hidraw*`, so unlike the libusb backend the
+//! kernel driver stays attached and the tools don't need to run as root; a
+//! udev rule granting access to the hidraw node is enough.
+//!
+//! Only available with the `hidapi` cargo feature.
+
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::fmt;
+use std::time::Duration;
+use hidapi;
+
+use errors::*;
+use super::Backend;
+use devices::{Model, CORSAIR_VENDOR_ID};
+use protocol::usbhid::PACKET_SIZE;
+
+/// hidapi reports are prefixed with their report number, which is always zero
+/// for Corsair Link devices.
+const REPORT_NUMBER: u8 = 0x00;
+
+/// A supported device found through hidapi.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    /// The platform-specific device path, such as `/dev/hidraw3` on Linux.
+    pub path: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub model: Model,
+    pub product_name: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+/// Lists every supported Corsair Link device visible to hidapi.
+pub fn devices(api: &hidapi::HidApi) -> Vec<DeviceInfo> {
+    api.device_list().filter_map(|device| {
+        if device.vendor_id() != CORSAIR_VENDOR_ID {
+            return None;
+        }
+        Model::from_product_id(device.product_id()).map(|model| DeviceInfo {
+            path: device.path().to_string_lossy().into_owned(),
+            vendor_id: device.vendor_id(),
+            product_id: device.product_id(),
+            model,
+            product_name: device.product_string().map(|s| s.to_string()),
+            serial_number: device.serial_number().map(|s| s.to_string()),
+        })
+    }).collect()
+}
+
+pub struct Device {
+    dev: hidapi::HidDevice,
+}
+
+impl fmt::Debug for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "hidraw backend")
+    }
+}
+
+impl Device {
+    /// Opens the first device with the given vendor and product IDs.
+    pub fn open(api: &hidapi::HidApi, vendor_id: u16, product_id: u16) -> Result<Device> {
+        // hidapi's errors don't say whether the device is missing or just
+        // couldn't be opened, e.g. for lack of permission, so look for it first
+        if !api.device_list().any(|device| device.vendor_id() == vendor_id && device.product_id() == product_id) {
+            return Err(TransportError::NoDevice.into());
+        }
+        Ok(Device { dev: api.open(vendor_id, product_id)? })
+    }
+
+    /// Opens the device at a path returned by `devices`.
+    pub fn open_path(api: &hidapi::HidApi, path: &str) -> Result<Device> {
+        let path = match CString::new(path) {
+            Ok(path) => path,
+            Err(_) => return Err(Error::invalid_argument("path", "device path contains a null byte")),
+        };
+        Ok(Device { dev: api.open_path(&path)? })
+    }
+}
+
+impl Backend for Device {
+    fn write_report(&mut self, data: &[u8], _timeout: Duration) -> Result<usize> {
+        if data.len() > PACKET_SIZE {
+            return Err(ProtocolError::PacketTooLarge { len: data.len() }.into());
+        }
+
+        let mut report = [0u8; PACKET_SIZE + 1];
+        report[0] = REPORT_NUMBER;
+        report[1..data.len() + 1].copy_from_slice(data);
+        self.dev.write(&report[..])?;
+        Ok(data.len())
+    }
+
+    fn read_report(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let timeout_ms = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        match self.dev.read_timeout(buf, timeout_ms)? {
+            0 => Err(TransportError::NoResponse.into()),
+            len => Ok(len),
+        }
+    }
+}
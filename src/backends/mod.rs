@@ -1 +1,140 @@
+//! # Transports
+//!
+//! A backend moves raw 64-byte HID reports between the host and a device, or
+//! for the Asetek-based coolers, the messages exchanged over bulk endpoints.
+//! The device drivers are generic over the `Backend` trait, so the same
+//! driver can talk to real hardware through libusb or to anything else that
+//! can exchange reports.
+
+use hex_slice::AsHex;
+use log::Level;
+
+use std::time::Duration;
+use errors::*;
+use protocol::usbhid as protocol;
+
 pub mod usbhid;
+pub mod usbbulk;
+#[cfg(feature = "hidapi")]
+pub mod hidraw;
+pub mod simulator;
+pub mod psu_simulator;
+pub mod asetek_simulator;
+pub mod replay;
+pub mod recorder;
+
+/// Log target for wire-level tracing of every packet sent and received, e.g.
+/// `RUST_LOG=corsairlink::wire=trace`.
+pub const WIRE_LOG_TARGET: &str = "corsairlink::wire";
+
+pub const DEFAULT_READ_TIMEOUT: u64 = 1000;
+pub const DEFAULT_WRITE_TIMEOUT: u64 = 1000;
+
+/// How many reports to read while waiting for the response to a packet before
+/// giving up. Devices sometimes have a stale report queued from a previous
+/// exchange.
+const MAX_RESPONSE_READS: usize = 3;
+
+pub trait Backend {
+    /// Writes a single output report, returning the number of bytes written.
+    fn write_report(&mut self, data: &[u8], timeout: Duration) -> Result<usize>;
+
+    /// Reads a single input report into `buf`, returning the number of bytes read.
+    fn read_report(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize>;
+
+    fn read_timeout(&self) -> Duration {
+        Duration::from_millis(DEFAULT_READ_TIMEOUT)
+    }
+
+    fn write_timeout(&self) -> Duration {
+        Duration::from_millis(DEFAULT_WRITE_TIMEOUT)
+    }
+
+    /// Writes an encoded packet and reads back its response, skipping over any
+    /// reports that don't start with the packet's first command ID.
+    fn write_packet<R: protocol::Register, V: protocol::Value<R>>(&mut self, packet: protocol::TxPacket<R,V>) -> Result<protocol::RxPacket<R, V>> where Self: Sized {
+        let encoded = packet.encode()?;
+        let (read_timeout, write_timeout) = (self.read_timeout(), self.write_timeout());
+        if log_enabled!(target: WIRE_LOG_TARGET, Level::Trace) {
+            let commands: Vec<String> = packet.commands().iter().map(|c| c.to_string()).collect();
+            trace!(target: WIRE_LOG_TARGET,
+                   direction = "tx", first_command_id = packet.first_command_id(), commands:% = commands.join(" ");
+                   "{:x}", encoded.as_hex());
+        }
+        self.write_report(&encoded[..], write_timeout)?;
+
+        let mut buf: Vec<u8> = vec![0u8; protocol::PACKET_SIZE];
+        let mut len = 0;
+        for _ in 0..MAX_RESPONSE_READS {
+            // Only the bytes just read belong to this report; the rest of the
+            // buffer may still hold a skipped one
+            len = self.read_report(buf.as_mut_slice(), read_timeout)?;
+            let report = &buf[..len];
+            trace!(target: WIRE_LOG_TARGET, direction = "rx", command_id:? = report.first(); "{:x}", report.as_hex());
+            match report.first() {
+                Some(&command_id) if command_id == encoded[1] => break,
+                Some(&command_id) =>
+                    debug!(target: WIRE_LOG_TARGET, "Skipping report for command {} while waiting for {}", command_id, encoded[1]),
+                None => debug!(target: WIRE_LOG_TARGET, "Skipping empty report while waiting for {}", encoded[1]),
+            }
+        }
+
+        let rx = protocol::RxPacket::decode(packet, &buf[..len])?;
+        trace!(target: WIRE_LOG_TARGET, direction = "rx", decoded:? = rx; "Decoded response");
+        Ok(rx)
+    }
+}
+
+impl<B: Backend + ?Sized> Backend for Box<B> {
+    fn write_report(&mut self, data: &[u8], timeout: Duration) -> Result<usize> {
+        (**self).write_report(data, timeout)
+    }
+
+    fn read_report(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        (**self).read_report(buf, timeout)
+    }
+
+    fn read_timeout(&self) -> Duration {
+        (**self).read_timeout()
+    }
+
+    fn write_timeout(&self) -> Duration {
+        (**self).write_timeout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use devices::cooler::h110i::{Register, RegisterValue};
+
+    /// A backend that answers with a fixed sequence of reports, each of
+    /// which may be shorter than a full packet.
+    struct Reports(VecDeque<Vec<u8>>);
+
+    impl Backend for Reports {
+        fn write_report(&mut self, data: &[u8], _timeout: Duration) -> Result<usize> {
+            Ok(data.len())
+        }
+
+        fn read_report(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+            let report = self.0.pop_front().ok_or(TransportError::NoResponse)?;
+            buf[..report.len()].copy_from_slice(&report);
+            Ok(report.len())
+        }
+    }
+
+    #[test]
+    fn short_read_does_not_reuse_stale_bytes() {
+        // A stale response for command 19 that would satisfy the read, then a
+        // truncated one for command 20
+        let mut backend = Reports(vec![vec![19, 0x09, 0xd2, 0x04], vec![20, 0x09]].into_iter().collect());
+        let packet: protocol::TxPacket<Register, RegisterValue> = protocol::TxPacket::new(20, vec![protocol::Command::Read(Register::FanRPM)]);
+
+        match backend.write_packet(packet) {
+            Err(Error::Decode(DecodeError::TruncatedPacket { expected: 4, actual: 2 })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! # HX750i simulator
+//!
+//! A software model of an HX750i behind its Corsair Link dongle, answering
+//! the same read and write requests as the real PSU. Readings are held as
+//! plain numbers so that tests can set up whatever load they like.
+//!
+//! ```
+//! use corsairlink::backends::psu_simulator::PsuSimulator;
+//! use corsairlink::devices::psu::hx750i;
+//!
+//! let mut psu = hx750i::Device::new(PsuSimulator::new());
+//! psu.get_metadata().unwrap();
+//! assert_eq!(psu.model(), "HX750i");
+//! assert_eq!(psu.rail(hx750i::Rail::V5).unwrap().voltage, 5.0);
+//! ```
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use errors::*;
+use backends::Backend;
+use protocol::pmbus::{Command, Flag, Value, INIT, REPORT_SIZE};
+
+#[derive(Clone, Debug)]
+pub struct Rail {
+    pub voltage: f64,
+    pub current: f64,
+    pub power: f64,
+    /// The over-current limit in amps.
+    pub ocp_limit: f64,
+}
+
+#[derive(Debug)]
+pub struct PsuSimulator {
+    pub model: String,
+    pub serial_number: String,
+
+    pub input_voltage: f64,
+    pub input_power: f64,
+    /// The +12V, +5V and +3.3V rails, in page order.
+    pub rails: Vec<Rail>,
+    pub temperatures: [f64; 2],
+    pub fan_rpm: f64,
+    /// The raw `FAN_CONTROL_MODE` and `FAN_COMMAND_1` bytes.
+    pub fan_control_mode: u8,
+    pub fan_duty: u8,
+    /// The raw `OCP_MODE` byte.
+    pub ocp_mode: u8,
+    /// Uptimes in seconds.
+    pub uptime: u32,
+    pub total_uptime: u32,
+
+    /// Set once the dongle has been sent its wake-up request.
+    pub initialized: bool,
+    /// Commands whose writes are acknowledged but have no effect, like a
+    /// PSU that refuses a setting without reporting an error.
+    pub ignored_writes: Vec<Command>,
+    page: u8,
+
+    responses: VecDeque<[u8; REPORT_SIZE]>,
+}
+
+impl Default for PsuSimulator {
+    fn default() -> PsuSimulator {
+        PsuSimulator::new()
+    }
+}
+
+impl PsuSimulator {
+    /// Creates a simulator of an HX750i delivering about 200W.
+    pub fn new() -> PsuSimulator {
+        PsuSimulator {
+            model: "HX750i".to_string(),
+            serial_number: "SIMULATED".to_string(),
+
+            input_voltage: 230.0,
+            input_power: 220.0,
+            rails: vec![
+                Rail { voltage: 12.0, current: 15.0, power: 180.0, ocp_limit: 40.0 },
+                Rail { voltage: 5.0, current: 2.0, power: 10.0, ocp_limit: 20.0 },
+                Rail { voltage: 3.3, current: 2.5, power: 8.25, ocp_limit: 20.0 },
+            ],
+            temperatures: [38.5, 32.0],
+            fan_rpm: 0.0,
+            fan_control_mode: 0x00,
+            fan_duty: 0,
+            ocp_mode: 0x01,
+            uptime: 3600,
+            total_uptime: 360000,
+
+            initialized: false,
+            ignored_writes: Vec::new(),
+            page: 0,
+
+            responses: VecDeque::new(),
+        }
+    }
+
+    fn rail(&self) -> Result<&Rail> {
+        self.rails.get(self.page as usize)
+            .ok_or_else(|| TransportError::Backend(format!("page {} does not exist", self.page)).into())
+    }
+
+    fn rail_mut(&mut self) -> Result<&mut Rail> {
+        let page = self.page;
+        self.rails.get_mut(page as usize)
+            .ok_or_else(|| TransportError::Backend(format!("page {} does not exist", page)).into())
+    }
+
+    fn read(&self, command: Command) -> Result<Value> {
+        Ok(match command {
+            Command::Page => Value::Byte(self.page),
+            Command::FanCommand1 => Value::Byte(self.fan_duty),
+            Command::IoutOcFaultLimit => Value::Linear11(self.rail()?.ocp_limit),
+            Command::ReadVin => Value::Linear11(self.input_voltage),
+            Command::ReadVout => Value::Linear11(self.rail()?.voltage),
+            Command::ReadIout => Value::Linear11(self.rail()?.current),
+            Command::ReadTemperature1 => Value::Linear11(self.temperatures[0]),
+            Command::ReadTemperature2 => Value::Linear11(self.temperatures[1]),
+            Command::ReadFanSpeed1 => Value::Linear11(self.fan_rpm),
+            Command::ReadPout => Value::Linear11(self.rail()?.power),
+            Command::MfrModel => Value::String(self.model.clone()),
+            Command::MfrSerial => Value::String(self.serial_number.clone()),
+            Command::ReadTotalUptime => Value::Seconds(self.total_uptime),
+            Command::ReadUptime => Value::Seconds(self.uptime),
+            Command::ReadInputPower => Value::Linear11(self.input_power),
+            Command::OcpMode => Value::Byte(self.ocp_mode),
+            Command::FanControlMode => Value::Byte(self.fan_control_mode),
+            _ => return Err(TransportError::Backend(format!("reading {:?} is not simulated", command)).into()),
+        })
+    }
+
+    fn write(&mut self, command: Command, value: Value) -> Result<()> {
+        if self.ignored_writes.contains(&command) {
+            return Ok(());
+        }
+        match (command, value) {
+            (Command::Page, Value::Byte(page)) => self.page = page,
+            (Command::FanCommand1, Value::Byte(duty)) => self.fan_duty = duty,
+            (Command::IoutOcFaultLimit, Value::Linear11(limit)) => self.rail_mut()?.ocp_limit = limit,
+            (Command::OcpMode, Value::Byte(mode)) => self.ocp_mode = mode,
+            (Command::FanControlMode, Value::Byte(mode)) => self.fan_control_mode = mode,
+            _ => return Err(TransportError::Backend(format!("writing {:?} is not simulated", command)).into()),
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, request: &[u8]) -> Result<[u8; REPORT_SIZE]> {
+        if request.len() < 2 {
+            return Err(TransportError::Backend(format!("request of {} bytes is too short", request.len())).into());
+        }
+
+        let mut response = [0u8; REPORT_SIZE];
+        response[0] = request[0];
+        response[1] = request[1];
+
+        if request[..2] == INIT {
+            self.initialized = true;
+            return Ok(response);
+        }
+        if !self.initialized {
+            return Err(TransportError::Backend("request before initialization".to_string()).into());
+        }
+
+        let command = Command::from_u8(request[1])
+            .ok_or_else(|| Error::from(TransportError::Backend(format!("unknown command 0x{:02x}", request[1]))))?;
+        if request[0] == Flag::Write as u8 {
+            let value = Value::decode(command, &request[2..])?;
+            self.write(command, value)?;
+        } else if request[0] == Flag::Read as u8 {
+            self.read(command)?.encode(command, &mut response[2..])?;
+        } else {
+            return Err(TransportError::Backend(format!("unknown request flag 0x{:02x}", request[0])).into());
+        }
+        Ok(response)
+    }
+}
+
+impl Backend for PsuSimulator {
+    fn write_report(&mut self, data: &[u8], _timeout: Duration) -> Result<usize> {
+        let response = self.execute(data)?;
+        self.responses.push_back(response);
+        Ok(data.len())
+    }
+
+    fn read_report(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        match self.responses.pop_front() {
+            Some(response) => {
+                let len = buf.len().min(response.len());
+                buf[..len].copy_from_slice(&response[..len]);
+                Ok(len)
+            },
+            None => Err(TransportError::NoResponse.into()),
+        }
+    }
+}
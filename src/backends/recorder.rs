@@ -0,0 +1,108 @@
+//! # Recording USB traffic
+//!
+//! `Recorder` wraps another backend and logs every report it exchanges in the
+//! usbmon text format, so that a session against real hardware can later be
+//! played back with `Replay`. Unlike the kernel's usbmon interface, complete
+//! reports are written rather than just their first 32 bytes.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use backends::Backend;
+
+/// Bus and device numbers written into the capture. They only need to be
+/// consistent within a capture, as replaying ignores them.
+const CAPTURE_BUS: u8 = 1;
+const CAPTURE_DEVICE: u8 = 1;
+
+#[derive(Debug)]
+pub struct Recorder<B, W: Write> {
+    backend: B,
+    out: W,
+    started: Instant,
+    tag: u32,
+}
+
+impl<B: Backend> Recorder<B, BufWriter<File>> {
+    /// Records to a new capture file at `path`, replacing any existing file.
+    pub fn create<P: AsRef<Path>>(backend: B, path: P) -> Result<Recorder<B, BufWriter<File>>> {
+        Ok(Recorder::new(backend, BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<B: Backend, W: Write> Recorder<B, W> {
+    pub fn new(backend: B, out: W) -> Recorder<B, W> {
+        Recorder { backend, out, started: Instant::now(), tag: 0 }
+    }
+
+    pub fn into_inner(self) -> (B, W) {
+        (self.backend, self.out)
+    }
+
+    fn record(&mut self, event: &str, pipe: &str, setup: &str, data: &[u8]) -> Result<()> {
+        let elapsed = self.started.elapsed();
+        let timestamp = elapsed.as_secs() * 1_000_000 + elapsed.subsec_micros() as u64;
+
+        let words: Vec<String> = data.chunks(4).map(|word| {
+            word.iter().map(|b| format!("{:02x}", b)).collect()
+        }).collect();
+
+        writeln!(self.out, "{:08x} {} {} {}:{}:{:03}:{} {} {} = {}",
+                 self.tag, timestamp as u32, event, pipe, CAPTURE_BUS, CAPTURE_DEVICE,
+                 if pipe == "Co" { 0 } else { 1 }, setup, data.len(), words.join(" "))?;
+        self.out.flush()?;
+        self.tag = self.tag.wrapping_add(1);
+        Ok(())
+    }
+}
+
+impl<B: Backend, W: Write> Backend for Recorder<B, W> {
+    fn write_report(&mut self, data: &[u8], timeout: Duration) -> Result<usize> {
+        let written = self.backend.write_report(data, timeout)?;
+        let setup = format!("s 21 09 0200 0000 {:04x}", data.len());
+        self.record("S", "Co", &setup, data)?;
+        Ok(written)
+    }
+
+    fn read_report(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let read = self.backend.read_report(buf, timeout)?;
+        self.record("C", "Ii", "0", &buf[..read])?;
+        Ok(read)
+    }
+
+    fn read_timeout(&self) -> Duration {
+        self.backend.read_timeout()
+    }
+
+    fn write_timeout(&self) -> Duration {
+        self.backend.write_timeout()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backends::replay::Replay;
+    use backends::simulator::Simulator;
+    use devices::cooler::h110i::Device;
+
+    #[test]
+    fn recording_replays_the_same_session() {
+        let mut cooler = Device::new(Recorder::new(Simulator::new(), Vec::new()));
+        cooler.get_metadata().unwrap();
+        cooler.poll_fans().unwrap();
+        cooler.poll_leds().unwrap();
+        let recorded = cooler.status();
+        let capture = cooler.backend().out.clone();
+
+        let mut cooler = Device::new(Replay::from_usbmon(&capture[..]).unwrap());
+        cooler.get_metadata().unwrap();
+        cooler.poll_fans().unwrap();
+        cooler.poll_leds().unwrap();
+        assert_eq!(cooler.status(), recorded);
+        assert!(cooler.backend().is_finished());
+    }
+}
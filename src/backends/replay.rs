@@ -0,0 +1,355 @@
+//! # Replaying captured USB traffic
+//!
+//! The `Replay` backend plays back the reports exchanged in a USB capture, so
+//! that traffic recorded from a real device can drive the drivers without the
+//! hardware attached. Two capture formats are understood:
+//!
+//! * The Linux usbmon text format, as read from
+//!   `/sys/kernel/debug/usb/usbmon/<bus>u` or written by `Recorder`.
+//! * pcapng files captured from a `usbmon` interface by Wireshark or tcpdump
+//!   (link types `LINKTYPE_USB_LINUX` and `LINKTYPE_USB_LINUX_MMAPPED`).
+//!
+//! Only the reports are kept: HID SET_REPORT control transfers and interrupt
+//! OUT transfers become `Report::Out`, and completed interrupt IN transfers
+//! become `Report::In`. Everything else in the capture is ignored.
+//!
+//! Note that the kernel's usbmon text interface only shows the first 32 bytes
+//! of each transfer; the remainder of such reports replays as zeroes.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use hex_slice::AsHex;
+
+use errors::*;
+use backends::Backend;
+
+const PCAPNG_SECTION_HEADER: u32 = 0x0A0D0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const PCAPNG_SIMPLE_PACKET: u32 = 0x00000003;
+const PCAPNG_ENHANCED_PACKET: u32 = 0x00000006;
+
+const LINKTYPE_USB_LINUX: u16 = 189;
+const LINKTYPE_USB_LINUX_MMAPPED: u16 = 220;
+
+const USBMON_EVENT_SUBMIT: u8 = b'S';
+const USBMON_EVENT_COMPLETE: u8 = b'C';
+const USBMON_TRANSFER_INTERRUPT: u8 = 1;
+const USBMON_TRANSFER_CONTROL: u8 = 2;
+const USBMON_ENDPOINT_IN: u8 = 0x80;
+
+const HID_SET_REPORT_REQUEST_TYPE: u8 = 0x21;
+const HID_SET_REPORT: u8 = 0x09;
+
+/// A single report captured on the wire, in the direction it travelled
+/// relative to the host.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Report {
+    Out(Vec<u8>),
+    In(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub struct Replay {
+    reports: VecDeque<Report>,
+    verify_writes: bool,
+}
+
+impl Replay {
+    pub fn new(reports: Vec<Report>) -> Replay {
+        Replay { reports: reports.into_iter().collect(), verify_writes: true }
+    }
+
+    /// Opens a capture file, detecting whether it is pcapng or usbmon text.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Replay> {
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; 4];
+        let is_pcapng = match file.read_exact(&mut magic) {
+            Ok(()) => LittleEndian::read_u32(&magic) == PCAPNG_SECTION_HEADER,
+            Err(_) => false,
+        };
+        file.seek(SeekFrom::Start(0))?;
+
+        if is_pcapng {
+            Replay::from_pcapng(file)
+        } else {
+            Replay::from_usbmon(BufReader::new(file))
+        }
+    }
+
+    pub fn from_usbmon<R: BufRead>(reader: R) -> Result<Replay> {
+        Ok(Replay::new(parse_usbmon(reader)?))
+    }
+
+    pub fn from_pcapng<R: Read>(reader: R) -> Result<Replay> {
+        Ok(Replay::new(parse_pcapng(reader)?))
+    }
+
+    /// When enabled (the default), every report written to the backend must
+    /// match the next captured outgoing report. Disable this to replay
+    /// responses regardless of what the driver sends, e.g. when the capture
+    /// used different command IDs.
+    pub fn verify_writes(mut self, verify: bool) -> Replay {
+        self.verify_writes = verify;
+        self
+    }
+
+    pub fn reports(&self) -> &VecDeque<Report> {
+        &self.reports
+    }
+
+    /// Returns true once every captured report has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.reports.is_empty()
+    }
+}
+
+/// Strips the zero padding from the end of a report, so that reports can be
+/// compared regardless of whether they were padded out to the full report
+/// size.
+fn trim_padding(data: &[u8]) -> &[u8] {
+    let len = data.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    &data[..len]
+}
+
+impl Backend for Replay {
+    fn write_report(&mut self, data: &[u8], _timeout: Duration) -> Result<usize> {
+        // Input reports that were captured but never read by the original
+        // program are dropped
+        loop {
+            match self.reports.pop_front() {
+                Some(Report::In(_)) => continue,
+                Some(Report::Out(expected)) => {
+                    if self.verify_writes && trim_padding(&expected) != trim_padding(data) {
+                        return Err(TransportError::Backend(format!("Replay: wrote {:x} but the capture has {:x}",
+                                                                  data.as_hex(), expected.as_hex())).into());
+                    }
+                    return Ok(data.len());
+                },
+                None => return Err(TransportError::NoResponse.into()),
+            }
+        }
+    }
+
+    fn read_report(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        match self.reports.pop_front() {
+            Some(Report::In(data)) => {
+                for b in buf.iter_mut() {
+                    *b = 0;
+                }
+                let len = buf.len().min(data.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                Ok(buf.len())
+            },
+            Some(report) => {
+                self.reports.push_front(report);
+                Err(TransportError::NoResponse.into())
+            },
+            None => Err(TransportError::NoResponse.into()),
+        }
+    }
+}
+
+fn parse_hex_words<'a, I: Iterator<Item = &'a str>>(words: I) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for word in words {
+        if word.len() % 2 != 0 {
+            return Err(DecodeError::InvalidCapture(format!("Invalid usbmon data word {:?}", word)).into());
+        }
+        for i in (0..word.len()).step_by(2) {
+            match u8::from_str_radix(&word[i..i + 2], 16) {
+                Ok(b) => data.push(b),
+                Err(_) => return Err(DecodeError::InvalidCapture(format!("Invalid usbmon data word {:?}", word)).into()),
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Parses the reports out of a usbmon text log.
+///
+/// Each line is an event in the form
+///
+/// ```text
+/// <tag> <timestamp> <S|C|E> <type><dir>:<bus>:<device>:<endpoint> <setup or status> <length> <= data...>
+/// ```
+pub fn parse_usbmon<R: BufRead>(reader: R) -> Result<Vec<Report>> {
+    let mut reports = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 5 {
+            continue;
+        }
+
+        let event = fields[2];
+        let pipe = match fields[3].get(0..2) {
+            Some(pipe) => pipe,
+            None => continue,
+        };
+        let data_start = match fields.iter().position(|f| *f == "=") {
+            Some(i) => i + 1,
+            None => continue,
+        };
+        let data = parse_hex_words(fields[data_start..].iter().cloned())?;
+
+        match (event, pipe) {
+            // Control submissions carry the setup packet as "s bmRequestType bRequest ..."
+            ("S", "Co") if fields[4] == "s" && fields.len() > 6 &&
+                fields[5] == format!("{:02x}", HID_SET_REPORT_REQUEST_TYPE) &&
+                fields[6] == format!("{:02x}", HID_SET_REPORT) => reports.push(Report::Out(data)),
+            ("S", "Io") => reports.push(Report::Out(data)),
+            ("C", "Ii") => reports.push(Report::In(data)),
+            _ => (),
+        }
+    }
+
+    Ok(reports)
+}
+
+/// Decodes a single Linux usbmon packet, as captured in pcapng files, into a
+/// report.
+fn parse_usbmon_packet<B: ByteOrder>(packet: &[u8], header_len: usize) -> Option<Report> {
+    if packet.len() < header_len {
+        return None;
+    }
+
+    let event = packet[8];
+    let transfer_type = packet[9];
+    let endpoint = packet[10];
+    let setup_present = packet[14] == 0;
+    let data_len = B::read_u32(&packet[36..40]) as usize;
+    let setup = &packet[40..48];
+
+    let end = (header_len + data_len).min(packet.len());
+    let data = packet[header_len..end].to_vec();
+    if data.is_empty() {
+        return None;
+    }
+
+    match (event, transfer_type, endpoint & USBMON_ENDPOINT_IN) {
+        (USBMON_EVENT_SUBMIT, USBMON_TRANSFER_CONTROL, 0) => {
+            if setup_present && setup[0] == HID_SET_REPORT_REQUEST_TYPE && setup[1] == HID_SET_REPORT {
+                Some(Report::Out(data))
+            } else {
+                None
+            }
+        },
+        (USBMON_EVENT_SUBMIT, USBMON_TRANSFER_INTERRUPT, 0) => Some(Report::Out(data)),
+        (USBMON_EVENT_COMPLETE, USBMON_TRANSFER_INTERRUPT, USBMON_ENDPOINT_IN) => Some(Report::In(data)),
+        _ => None,
+    }
+}
+
+fn parse_pcapng_packets<B: ByteOrder>(data: &[u8]) -> Result<Vec<Report>> {
+    let mut reports = Vec::new();
+    let mut link_types: Vec<u16> = Vec::new();
+
+    let mut i = 0;
+    while i + 12 <= data.len() {
+        let block_type = B::read_u32(&data[i..i + 4]);
+        let block_len = B::read_u32(&data[i + 4..i + 8]) as usize;
+        if block_len < 12 || i + block_len > data.len() {
+            return Err(DecodeError::InvalidCapture("Truncated pcapng block".to_string()).into());
+        }
+        let body = &data[i + 8..i + block_len - 4];
+
+        match block_type {
+            PCAPNG_SECTION_HEADER => link_types.clear(),
+            PCAPNG_INTERFACE_DESCRIPTION if body.len() >= 2 => link_types.push(B::read_u16(&body[0..2])),
+            PCAPNG_ENHANCED_PACKET | PCAPNG_SIMPLE_PACKET => {
+                let (interface, packet) = if block_type == PCAPNG_ENHANCED_PACKET {
+                    if body.len() < 20 {
+                        return Err(DecodeError::InvalidCapture("Truncated pcapng enhanced packet block".to_string()).into());
+                    }
+                    let captured_len = B::read_u32(&body[12..16]) as usize;
+                    (B::read_u32(&body[0..4]) as usize, &body[20..(20 + captured_len).min(body.len())])
+                } else {
+                    (0, &body[4.min(body.len())..])
+                };
+
+                let report = match link_types.get(interface) {
+                    Some(&LINKTYPE_USB_LINUX) => parse_usbmon_packet::<B>(packet, 48),
+                    Some(&LINKTYPE_USB_LINUX_MMAPPED) => parse_usbmon_packet::<B>(packet, 64),
+                    Some(link_type) => return Err(DecodeError::InvalidCapture(format!("Unsupported pcapng link type {}", link_type)).into()),
+                    None => return Err(DecodeError::InvalidCapture("pcapng packet refers to an unknown interface".to_string()).into()),
+                };
+                if let Some(report) = report {
+                    reports.push(report);
+                }
+            },
+            _ => (),
+        }
+
+        i += block_len;
+    }
+
+    Ok(reports)
+}
+
+/// Parses the reports out of a pcapng capture of a Linux usbmon interface.
+pub fn parse_pcapng<R: Read>(mut reader: R) -> Result<Vec<Report>> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    if data.len() < 12 || LittleEndian::read_u32(&data[0..4]) != PCAPNG_SECTION_HEADER {
+        return Err(DecodeError::InvalidCapture("Not a pcapng file".to_string()).into());
+    }
+
+    // The byte order of the whole section is given by the magic number that
+    // follows the section header's length
+    if LittleEndian::read_u32(&data[8..12]) == PCAPNG_BYTE_ORDER_MAGIC {
+        parse_pcapng_packets::<LittleEndian>(&data[..])
+    } else if BigEndian::read_u32(&data[8..12]) == PCAPNG_BYTE_ORDER_MAGIC {
+        parse_pcapng_packets::<BigEndian>(&data[..])
+    } else {
+        Err(DecodeError::InvalidCapture("Invalid pcapng byte order magic".to_string()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devices::cooler::h110i::{CoolerModel, Device, Register};
+
+    /// Captures of an H110i having its metadata read and its fans polled.
+    const USBMON_CAPTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/captures/h110i.usbmon");
+    const PCAPNG_CAPTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/captures/h110i.pcapng");
+
+    fn check_session(replay: Replay) {
+        let mut cooler = Device::new(replay);
+        cooler.get_metadata().unwrap();
+        assert_eq!(cooler.model(), Some(CoolerModel::H110i));
+        assert_eq!(cooler.firmware_version(), "2.0.00");
+        assert_eq!(cooler.product_name(), "H110i");
+
+        cooler.poll_fans().unwrap();
+        assert_eq!(cooler.fan_speeds, vec![1100, 1150, 2100]);
+        assert!(cooler.backend().is_finished());
+    }
+
+    #[test]
+    fn replays_usbmon_capture() {
+        let replay = Replay::open(USBMON_CAPTURE).unwrap();
+        assert_eq!(replay.reports().len(), 4);
+        check_session(replay);
+    }
+
+    #[test]
+    fn replays_pcapng_capture() {
+        let replay = Replay::open(PCAPNG_CAPTURE).unwrap();
+        assert_eq!(replay.reports().len(), 4);
+        check_session(replay);
+    }
+
+    #[test]
+    fn rejects_writes_that_differ_from_capture() {
+        let mut cooler = Device::new(Replay::open(USBMON_CAPTURE).unwrap());
+        assert!(cooler.read_register(Register::Status).is_err());
+    }
+}
@@ -0,0 +1,495 @@
+//! # Corsair Link HID firmware simulator
+//!
+//! A software model of the H110i register file, or that of any other model in
+//! its family, that can stand in for real hardware. It parses the same
+//! `TxPacket` bytes that would be written to the cooler, applies them to an
+//! in-memory set of registers, and queues a 64-byte response in the format
+//! the firmware uses, echoing each command ID and opcode.
+//!
+//! The register state is exposed as raw bytes so that tests can set up the
+//! device however they like and inspect the result of any writes.
+//!
+//! ```
+//! use corsairlink::backends::simulator::Simulator;
+//! use corsairlink::devices::cooler::h110i;
+//!
+//! let mut cooler = h110i::Device::new(Simulator::new());
+//! cooler.get_metadata().unwrap();
+//! cooler.poll_fans().unwrap();
+//! assert_eq!(cooler.fan_speeds, vec![1100, 1150, 2100]);
+//!
+//! cooler.set_led_mode(h110i::LedMode::four_color_cycle_mode(7)).unwrap();
+//! assert_eq!(cooler.backend().leds[0].mode, 0x87);
+//! ```
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use byteorder::{ByteOrder, LittleEndian};
+
+use errors::*;
+use backends::Backend;
+use devices::cooler::h110i::{CoolerModel, Register};
+use protocol::usbhid::{self, Opcode, PACKET_SIZE};
+
+#[derive(Clone, Debug)]
+pub struct Led {
+    pub mode: u8,
+    pub color: [u8; 3],
+    pub temperature: u16,
+    pub temperature_mode_temps: [u8; 6],
+    pub temperature_mode_colors: [u8; 9],
+    pub cycle_colors: [u8; 12],
+}
+
+#[derive(Clone, Debug)]
+pub struct TempSensor {
+    /// Temperature in 1/256ths of a degree Celsius.
+    pub value: u16,
+    pub limit: u16,
+}
+
+#[derive(Clone, Debug)]
+pub struct Fan {
+    pub mode: u8,
+    pub fixed_pwm: u8,
+    pub fixed_rpm: u16,
+    pub ext_temp: u16,
+    pub rpm: u16,
+    pub max_recorded_rpm: u16,
+    pub under_speed_threshold: u16,
+    pub rpm_table: [u8; 10],
+    pub temp_table: [u8; 10],
+}
+
+impl Fan {
+    /// Creates a fan spinning at `rpm`, with the rest of its registers in
+    /// their power-on state.
+    pub fn new(mode: u8, rpm: u16) -> Fan {
+        Fan {
+            mode,
+            fixed_pwm: 0x80,
+            fixed_rpm: 1000,
+            ext_temp: 0,
+            rpm,
+            max_recorded_rpm: rpm,
+            under_speed_threshold: 600,
+            // 1000, 1200, 1400, 1600 and 1800 RPM
+            rpm_table: [0xe8, 0x03, 0xb0, 0x04, 0x78, 0x05, 0x40, 0x06, 0x08, 0x07],
+            // 25, 30, 35, 40 and 45°C
+            temp_table: [0x00, 0x19, 0x00, 0x1e, 0x00, 0x23, 0x00, 0x28, 0x00, 0x2d],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Simulator {
+    pub device_id: u8,
+    pub firmware_version: u16,
+    /// Raw contents of the ProductName block. The firmware precedes the
+    /// null-terminated name with a single byte that the driver skips.
+    pub product_name: [u8; 8],
+    pub status: u8,
+
+    pub leds: Vec<Led>,
+    pub temp_sensors: Vec<TempSensor>,
+    pub fans: Vec<Fan>,
+
+    led_select: u8,
+    temp_sensor_select: u8,
+    fan_select: u8,
+
+    responses: VecDeque<Vec<u8>>,
+}
+
+impl Default for Simulator {
+    fn default() -> Simulator {
+        Simulator::new()
+    }
+}
+
+impl Simulator {
+    /// Creates a simulator in the state of an H110i running firmware v2.0.00:
+    /// one LED, one coolant temperature sensor, two fans and the pump.
+    pub fn new() -> Simulator {
+        Simulator::for_model(CoolerModel::H110i)
+    }
+
+    /// Creates a simulator of any model in the Corsair Link HID family, with
+    /// the channels listed in its capability table. Fans spin at 1100 RPM and
+    /// up, and the pump, if there is one, at 2100 RPM.
+    pub fn for_model(model: CoolerModel) -> Simulator {
+        let capabilities = model.capabilities();
+        let product_name = match model {
+            CoolerModel::H80i => *b"\x04H80i\x00\x00\x00",
+            CoolerModel::H100i => *b"\x05H100i\x00\x00",
+            CoolerModel::CommanderMini => *b"\x04Mini\x00\x00\x00",
+            CoolerModel::H110i => *b"\x05H110i\x00\x00",
+        };
+
+        let fans = (0..capabilities.fan_count).map(|i| {
+            if capabilities.pump && i == capabilities.fan_count - 1 {
+                Fan::new(0x87, 2100)
+            } else {
+                Fan::new(0x87, 1100 + 50 * u16::from(i))
+            }
+        }).collect();
+
+        Simulator {
+            device_id: model.device_id(),
+            firmware_version: 0x2000,
+            product_name,
+            status: 0,
+
+            leds: vec![
+                Led {
+                    mode: 0x00,
+                    color: [0x00, 0x00, 0xff],
+                    temperature: 0,
+                    // 30, 40 and 50°C shown as green, yellow and red
+                    temperature_mode_temps: [0x00, 0x1e, 0x00, 0x28, 0x00, 0x32],
+                    temperature_mode_colors: [0x00, 0xff, 0x00, 0xff, 0xff, 0x00, 0xff, 0x00, 0x00],
+                    cycle_colors: [0; 12],
+                };
+                capabilities.led_count as usize
+            ],
+            temp_sensors: vec![TempSensor { value: 0x1e80, limit: 0x3c00 }; capabilities.temp_sensor_count as usize],
+            fans,
+
+            led_select: 0,
+            temp_sensor_select: 0,
+            fan_select: 0,
+
+            responses: VecDeque::new(),
+        }
+    }
+
+    fn led(&mut self) -> Result<&mut Led> {
+        let i = self.led_select as usize;
+        match self.leds.get_mut(i) {
+            Some(led) => Ok(led),
+            None => Err(TransportError::Backend(format!("Simulator: LED {} is selected but does not exist", i)).into()),
+        }
+    }
+
+    fn temp_sensor(&mut self) -> Result<&mut TempSensor> {
+        let i = self.temp_sensor_select as usize;
+        match self.temp_sensors.get_mut(i) {
+            Some(sensor) => Ok(sensor),
+            None => Err(TransportError::Backend(format!("Simulator: temperature sensor {} is selected but does not exist", i)).into()),
+        }
+    }
+
+    fn fan(&mut self) -> Result<&mut Fan> {
+        let i = self.fan_select as usize;
+        match self.fans.get_mut(i) {
+            Some(fan) => Ok(fan),
+            None => Err(TransportError::Backend(format!("Simulator: fan {} is selected but does not exist", i)).into()),
+        }
+    }
+
+    fn read_register(&mut self, register: Register) -> Result<Vec<u8>> {
+        let mut word = [0u8; 2];
+        Ok(match register {
+            Register::DeviceId => vec![self.device_id],
+            Register::FirmwareVersion => {
+                LittleEndian::write_u16(&mut word, self.firmware_version);
+                word.to_vec()
+            },
+            Register::ProductName => self.product_name.to_vec(),
+            Register::Status => vec![self.status],
+
+            Register::LedSelect => vec![self.led_select],
+            Register::LedCount => vec![self.leds.len() as u8],
+            Register::LedMode => vec![self.led()?.mode],
+            Register::LedColor => self.led()?.color.to_vec(),
+            Register::LedTemperatureColor => {
+                LittleEndian::write_u16(&mut word, self.led()?.temperature);
+                word.to_vec()
+            },
+            Register::LedTemperatureModeTemps => self.led()?.temperature_mode_temps.to_vec(),
+            Register::LedTemperatureModeColors => self.led()?.temperature_mode_colors.to_vec(),
+            Register::LedCycleColors => self.led()?.cycle_colors.to_vec(),
+
+            Register::TempSensorSelect => vec![self.temp_sensor_select],
+            Register::TempSensorCount => vec![self.temp_sensors.len() as u8],
+            Register::TempSensorValue => {
+                LittleEndian::write_u16(&mut word, self.temp_sensor()?.value);
+                word.to_vec()
+            },
+            Register::TempSensorLimit => {
+                LittleEndian::write_u16(&mut word, self.temp_sensor()?.limit);
+                word.to_vec()
+            },
+
+            Register::FanSelect => vec![self.fan_select],
+            Register::FanCount => vec![self.fans.len() as u8],
+            Register::FanMode => vec![self.fan()?.mode],
+            Register::FanFixedPWM => vec![self.fan()?.fixed_pwm],
+            Register::FanFixedRPM => {
+                LittleEndian::write_u16(&mut word, self.fan()?.fixed_rpm);
+                word.to_vec()
+            },
+            Register::FanReportExtTemp => {
+                LittleEndian::write_u16(&mut word, self.fan()?.ext_temp);
+                word.to_vec()
+            },
+            Register::FanRPM => {
+                LittleEndian::write_u16(&mut word, self.fan()?.rpm);
+                word.to_vec()
+            },
+            Register::FanMaxRecordedRPM => {
+                LittleEndian::write_u16(&mut word, self.fan()?.max_recorded_rpm);
+                word.to_vec()
+            },
+            Register::FanUnderSpeedThreshold => {
+                LittleEndian::write_u16(&mut word, self.fan()?.under_speed_threshold);
+                word.to_vec()
+            },
+            Register::FanRPMTable => self.fan()?.rpm_table.to_vec(),
+            Register::FanTempTable => self.fan()?.temp_table.to_vec(),
+        })
+    }
+
+    /// Applies a write to a register. Writes to read-only registers are
+    /// acknowledged by the firmware but have no effect.
+    fn write_register(&mut self, register: Register, data: &[u8]) -> Result<()> {
+        match register {
+            Register::LedSelect => self.led_select = data[0],
+            Register::LedMode => self.led()?.mode = data[0],
+            Register::LedColor => self.led()?.color.copy_from_slice(&data[0..3]),
+            Register::LedTemperatureColor => self.led()?.temperature = LittleEndian::read_u16(data),
+            Register::LedTemperatureModeTemps => self.led()?.temperature_mode_temps.copy_from_slice(&data[0..6]),
+            Register::LedTemperatureModeColors => self.led()?.temperature_mode_colors.copy_from_slice(&data[0..9]),
+            Register::LedCycleColors => self.led()?.cycle_colors.copy_from_slice(&data[0..12]),
+
+            Register::TempSensorSelect => self.temp_sensor_select = data[0],
+            Register::TempSensorLimit => self.temp_sensor()?.limit = LittleEndian::read_u16(data),
+
+            Register::FanSelect => self.fan_select = data[0],
+            Register::FanMode => {
+                // The fan detected bit is reported by the firmware, not set by the host
+                let fan = self.fan()?;
+                fan.mode = (fan.mode & 0x80) | (data[0] & 0x7f);
+            },
+            Register::FanFixedPWM => self.fan()?.fixed_pwm = data[0],
+            Register::FanFixedRPM => self.fan()?.fixed_rpm = LittleEndian::read_u16(data),
+            Register::FanReportExtTemp => self.fan()?.ext_temp = LittleEndian::read_u16(data),
+            Register::FanUnderSpeedThreshold => self.fan()?.under_speed_threshold = LittleEndian::read_u16(data),
+            Register::FanRPMTable => self.fan()?.rpm_table.copy_from_slice(&data[0..10]),
+            Register::FanTempTable => self.fan()?.temp_table.copy_from_slice(&data[0..10]),
+
+            _ => (),
+        };
+        Ok(())
+    }
+
+    /// Executes every command in an encoded packet, returning the response
+    /// report.
+    fn execute_packet(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        if data.is_empty() {
+            return Err(TransportError::Backend("Simulator: received an empty packet".to_string()).into());
+        }
+
+        let end = data[0] as usize + 1;
+        if end > data.len() {
+            return Err(TransportError::Backend("Simulator: packet length byte exceeds packet size".to_string()).into());
+        }
+
+        let mut response = Vec::with_capacity(PACKET_SIZE);
+        let mut i = 1;
+        while i < end {
+            if i + 3 > end {
+                return Err(TransportError::Backend("Simulator: truncated command".to_string()).into());
+            }
+            let (command_id, opcode, register) = (data[i], data[i + 1], data[i + 2]);
+            i += 3;
+
+            let opcode = match Opcode::from_u8(opcode) {
+                Some(opcode) => opcode,
+                None => return Err(TransportError::Backend(format!("Simulator: unknown opcode 0x{:02x}", opcode)).into()),
+            };
+            let register = match Register::from_u8(register) {
+                Some(register) => register,
+                None => return Err(Error::UnsupportedRegister(register)),
+            };
+
+            response.push(command_id);
+            response.push(opcode as u8);
+
+            let len = match opcode {
+                Opcode::ReadByte | Opcode::WriteByte => 1,
+                Opcode::ReadWord | Opcode::WriteWord => 2,
+                Opcode::ReadBlock | Opcode::WriteBlock => {
+                    if i >= end {
+                        return Err(TransportError::Backend("Simulator: block command is missing its length byte".to_string()).into());
+                    }
+                    i += 1;
+                    data[i - 1] as usize
+                },
+            };
+
+            match opcode {
+                Opcode::ReadByte | Opcode::ReadWord | Opcode::ReadBlock => {
+                    let mut value = self.read_register(register)?;
+                    value.resize(len, 0);
+                    if opcode == Opcode::ReadBlock {
+                        response.push(len as u8);
+                    }
+                    response.extend(value);
+                },
+                Opcode::WriteByte | Opcode::WriteWord | Opcode::WriteBlock => {
+                    if i + len > end {
+                        return Err(TransportError::Backend("Simulator: truncated write command".to_string()).into());
+                    }
+                    let mut value = data[i .. i + len].to_vec();
+                    value.resize(usbhid::Register::size(&register), 0);
+                    self.write_register(register, &value[..])?;
+                    i += len;
+                },
+            }
+        }
+
+        if response.len() > PACKET_SIZE {
+            return Err(TransportError::Backend("Simulator: response does not fit in a single report".to_string()).into());
+        }
+        response.resize(PACKET_SIZE, 0);
+        Ok(response)
+    }
+}
+
+impl Backend for Simulator {
+    fn write_report(&mut self, data: &[u8], _timeout: Duration) -> Result<usize> {
+        let response = self.execute_packet(data)?;
+        self.responses.push_back(response);
+        Ok(data.len())
+    }
+
+    fn read_report(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        match self.responses.pop_front() {
+            Some(response) => {
+                let len = buf.len().min(response.len());
+                buf[..len].copy_from_slice(&response[..len]);
+                Ok(len)
+            },
+            None => Err(TransportError::NoResponse.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devices::cooler::h110i::{Device, FanProfile, LedMode, RgbColor, TempChannel};
+
+    fn cooler() -> Device<Simulator> {
+        let mut cooler = Device::new(Simulator::new());
+        cooler.get_metadata().unwrap();
+        cooler
+    }
+
+    #[test]
+    fn get_metadata() {
+        let cooler = cooler();
+        assert_eq!(cooler.device_id(), 0x42);
+        assert_eq!(cooler.model(), Some(CoolerModel::H110i));
+        assert_eq!(cooler.firmware_version(), "2.0.00");
+        assert_eq!(cooler.product_name(), "H110i");
+        assert_eq!((cooler.led_count(), cooler.temp_sensor_count(), cooler.fan_count()), (1, 1, 3));
+    }
+
+    #[test]
+    fn poll_leds() {
+        let mut cooler = cooler();
+        cooler.backend_mut().leds[0].mode = 0x47;
+        cooler.backend_mut().leds[0].cycle_colors = [0xff, 0, 0, 0, 0xff, 0, 0, 0, 0xff, 0x10, 0x20, 0x30];
+        cooler.poll_leds().unwrap();
+
+        assert_eq!(cooler.led_modes, vec![LedMode::TwoColorCycle(7)]);
+        assert_eq!(cooler.led_colors, vec![RgbColor(0x00, 0x00, 0xff)]);
+        assert_eq!(cooler.led_cycle_colors, vec![[
+            RgbColor(0xff, 0, 0), RgbColor(0, 0xff, 0), RgbColor(0, 0, 0xff), RgbColor(0x10, 0x20, 0x30),
+        ]]);
+    }
+
+    #[test]
+    fn poll_temperatures() {
+        let mut cooler = cooler();
+        cooler.poll_temperatures().unwrap();
+        assert_eq!(cooler.temperatures.len(), 1);
+        assert_eq!(cooler.temperatures[0].degrees_c(), 30.5);
+    }
+
+    #[test]
+    fn poll_fans() {
+        let mut cooler = cooler();
+        cooler.backend_mut().fans[1].rpm = 1234;
+        cooler.poll_fans().unwrap();
+
+        assert_eq!(cooler.fan_speeds, vec![1100, 1234, 2100]);
+        assert_eq!(cooler.fan_modes.len(), 3);
+        assert!(cooler.fan_modes[0].fan_detected());
+        assert_eq!(cooler.fan_modes[0].temp_channel(), TempChannel::InternalSensor);
+    }
+
+    #[test]
+    fn set_led_colors() {
+        let mut cooler = cooler();
+        let colors = [RgbColor(1, 2, 3), RgbColor(4, 5, 6), RgbColor(7, 8, 9), RgbColor(10, 11, 12)];
+        cooler.set_led_colors(0, colors).unwrap();
+        assert_eq!(cooler.backend().leds[0].cycle_colors, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+
+        cooler.poll_leds().unwrap();
+        assert_eq!(cooler.led_cycle_colors, vec![colors]);
+    }
+
+    #[test]
+    fn set_led_colors_rejects_missing_led() {
+        let mut cooler = cooler();
+        let colors = [RgbColor(0, 0, 0); 4];
+        assert!(cooler.set_led_colors(1, colors).is_err());
+    }
+
+    #[test]
+    fn set_led_mode() {
+        let mut cooler = cooler();
+        cooler.set_led_mode(LedMode::Temperature(TempChannel::Manual)).unwrap();
+        assert_eq!(cooler.backend().leds[0].mode, 0xc7);
+
+        cooler.poll_leds().unwrap();
+        assert_eq!(cooler.led_modes, vec![LedMode::Temperature(TempChannel::Manual)]);
+    }
+
+    #[test]
+    fn commander_mini_sensor_channels() {
+        let mut mini = Device::new(Simulator::for_model(CoolerModel::CommanderMini));
+        mini.get_metadata().unwrap();
+        assert_eq!(mini.temp_sensor_count(), 4);
+        // Fan 1 detected, in curve mode, following temperature channel 2
+        mini.backend_mut().fans[1].mode = 0xae;
+        mini.backend_mut().leds[0].mode = 0xc3;
+
+        mini.poll_fans().unwrap();
+        assert_eq!(mini.fan_modes[1].profile(), FanProfile::Custom);
+        assert_eq!(mini.fan_modes[1].temp_channel(), TempChannel::Sensor(2));
+        mini.poll_leds().unwrap();
+        assert_eq!(mini.led_modes, vec![LedMode::Temperature(TempChannel::Sensor(3))]);
+        mini.snapshot().unwrap();
+
+        mini.set_fan_temp_channel(0, TempChannel::Sensor(3)).unwrap();
+        mini.poll_fans().unwrap();
+        assert_eq!(mini.fan_modes[0].temp_channel(), TempChannel::Sensor(3));
+        assert_eq!(mini.fan_modes[0].profile(), FanProfile::ProfileDefault);
+    }
+
+    #[test]
+    fn sensor_channels_must_exist() {
+        let mut mini = Device::new(Simulator::for_model(CoolerModel::CommanderMini));
+        mini.get_metadata().unwrap();
+        assert!(mini.set_fan_temp_channel(0, TempChannel::Sensor(4)).is_err());
+        assert!(mini.set_fan_temp_channel(0, TempChannel::Sensor(0)).is_err());
+
+        let mut cooler = cooler();
+        assert!(cooler.set_led_mode(LedMode::Temperature(TempChannel::Sensor(1))).is_err());
+        assert_eq!(cooler.backend().leds[0].mode, 0x00);
+    }
+}
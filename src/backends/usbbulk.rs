@@ -0,0 +1,103 @@
+//! # libusb bulk transport
+//!
+//! The Asetek-based coolers aren't HID devices. They sit behind a Silicon
+//! Labs USBXpress bridge, which exchanges messages over a pair of bulk
+//! endpoints once it has been told, through vendor control requests, to flush
+//! its buffers and start accepting data.
+
+use std::fmt;
+use std::time::Duration;
+use errors::*;
+use libusb;
+
+use super::{Backend, DEFAULT_READ_TIMEOUT, DEFAULT_WRITE_TIMEOUT};
+use super::usbhid::{enumerate, DeviceInfo, Selector};
+
+const INTERFACE_NUMBER: u8 = 0;
+const BULK_OUT_ENDPOINT: u8 = 0x02;
+const BULK_IN_ENDPOINT: u8 = 0x82;
+
+const USBXPRESS_REQUEST: u8 = 0x02;
+const USBXPRESS_FLUSH_BUFFERS: u16 = 0x0001;
+const USBXPRESS_CLEAR_TO_SEND: u16 = 0x0002;
+
+pub struct Device<'a> {
+    dev: libusb::DeviceHandle<'a>,
+    read_timeout: Duration,
+    write_timeout: Duration,
+}
+
+impl<'a> fmt::Debug for Device<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "usbbulk backend")
+    }
+}
+
+impl<'a> Device<'a> {
+    /// Opens the supported device picked out by `selector`, returning it along
+    /// with its description.
+    pub fn open_selected(context: &'a libusb::Context, selector: &Selector) -> Result<(Device<'a>, DeviceInfo)> {
+        for (index, (device, info)) in enumerate(context)?.into_iter().enumerate() {
+            if selector.matches(index, &info) {
+                return Ok((Device::open_device(&device)?, info));
+            }
+        }
+
+        Err(TransportError::NoDevice.into())
+    }
+
+    fn open_device(device: &libusb::Device<'a>) -> Result<Device<'a>> {
+        let mut handle = device.open()?;
+        if handle.kernel_driver_active(INTERFACE_NUMBER)? {
+            handle.detach_kernel_driver(INTERFACE_NUMBER)?;
+        }
+        handle.claim_interface(INTERFACE_NUMBER)?;
+
+        let device = Device {
+            dev: handle,
+            read_timeout: Duration::from_millis(DEFAULT_READ_TIMEOUT),
+            write_timeout: Duration::from_millis(DEFAULT_WRITE_TIMEOUT),
+        };
+        device.usbxpress_request(USBXPRESS_FLUSH_BUFFERS)?;
+        device.usbxpress_request(USBXPRESS_CLEAR_TO_SEND)?;
+        Ok(device)
+    }
+
+    fn usbxpress_request(&self, value: u16) -> Result<()> {
+        self.dev.write_control(
+            libusb::request_type(libusb::Direction::Out, libusb::RequestType::Vendor, libusb::Recipient::Device),
+            USBXPRESS_REQUEST,
+            value,
+            0,
+            &[],
+            self.write_timeout,
+        )?;
+        Ok(())
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        self.write_timeout = timeout;
+    }
+}
+
+impl<'a> Backend for Device<'a> {
+    fn write_report(&mut self, data: &[u8], timeout: Duration) -> Result<usize> {
+        self.dev.write_bulk(BULK_OUT_ENDPOINT, data, timeout).map_err(|e| TransportError::Usb(e).into())
+    }
+
+    fn read_report(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.dev.read_bulk(BULK_IN_ENDPOINT, buf, timeout).map_err(|e| TransportError::Usb(e).into())
+    }
+
+    fn read_timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    fn write_timeout(&self) -> Duration {
+        self.write_timeout
+    }
+}
@@ -1,13 +1,12 @@
-use hex_slice::AsHex;
-
 use std::fmt;
+use std::fs;
+use std::path::Path;
 use std::time::Duration;
 use errors::*;
-use protocol::usbhid as protocol;
 use libusb;
 
-const DEFAULT_READ_TIMEOUT: u64 = 1000;
-const DEFAULT_WRITE_TIMEOUT: u64 = 1000;
+use super::{Backend, DEFAULT_READ_TIMEOUT, DEFAULT_WRITE_TIMEOUT};
+use devices::{Model, CORSAIR_VENDOR_ID};
 
 const HID_SET_REPORT: u8 = 0x09;
 const HID_REPORT_TYPE_OUTPUT: u16 = 0x02;
@@ -15,6 +14,120 @@ const HID_REPORT_NUMBER: u16 = 0x00;
 const INTERFACE_NUMBER: u8 = 0;
 const INTERRUPT_IN_ENDPOINT: u8 = 0x81;
 
+const STRING_DESCRIPTOR_TIMEOUT: u64 = 100;
+const SYSFS_USB_DEVICES: &str = "/sys/bus/usb/devices";
+
+/// A supported device found on the bus, which may or may not be open.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub bus: u8,
+    pub address: u8,
+    /// The physical port the device is plugged into, such as `1-4.2`, which
+    /// stays the same across reboots. Only available on Linux.
+    pub port_path: Option<String>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub model: Model,
+    pub product_name: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+/// Picks one device out of those returned by `devices`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selector {
+    /// The nth supported device, in enumeration order.
+    Index(usize),
+    BusAddress(u8, u8),
+    PortPath(String),
+    /// The first supported device of a model.
+    Model(Model),
+    /// The first supported device of any of these models.
+    Models(&'static [Model]),
+}
+
+impl Selector {
+    /// Returns true if this selects `info`, the `index`th device returned by
+    /// `devices`.
+    pub fn matches(&self, index: usize, info: &DeviceInfo) -> bool {
+        match *self {
+            Selector::Index(i) => i == index,
+            Selector::BusAddress(bus, address) => info.bus == bus && info.address == address,
+            Selector::PortPath(ref path) => info.port_path.as_ref() == Some(path),
+            Selector::Model(model) => info.model == model,
+            Selector::Models(models) => models.contains(&info.model),
+        }
+    }
+}
+
+/// Finds the sysfs name of the device at the given bus and address, which is
+/// its port path.
+fn port_path(bus: u8, address: u8) -> Option<String> {
+    let read_number = |path: &Path| -> Option<u8> {
+        fs::read_to_string(path).ok().and_then(|s| s.trim().parse().ok())
+    };
+
+    for entry in fs::read_dir(SYSFS_USB_DEVICES).ok()? {
+        let path = entry.ok()?.path();
+        if read_number(&path.join("busnum")) == Some(bus) && read_number(&path.join("devnum")) == Some(address) {
+            return path.file_name().and_then(|name| name.to_str()).map(|name| name.to_string());
+        }
+    }
+    None
+}
+
+/// Reads the product name and serial number string descriptors. These need
+/// the device to be opened, which may not be permitted, so failures are not
+/// treated as errors.
+fn read_strings(device: &libusb::Device, descriptor: &libusb::DeviceDescriptor) -> (Option<String>, Option<String>) {
+    let timeout = Duration::from_millis(STRING_DESCRIPTOR_TIMEOUT);
+    let handle = match device.open() {
+        Ok(handle) => handle,
+        Err(_) => return (None, None),
+    };
+    let language = match handle.read_languages(timeout).ok().and_then(|languages| languages.first().cloned()) {
+        Some(language) => language,
+        None => return (None, None),
+    };
+
+    (handle.read_product_string(language, descriptor, timeout).ok(),
+     handle.read_serial_number_string(language, descriptor, timeout).ok())
+}
+
+pub(crate) fn enumerate<'a>(context: &'a libusb::Context) -> Result<Vec<(libusb::Device<'a>, DeviceInfo)>> {
+    let mut found = Vec::new();
+
+    for device in context.devices()?.iter() {
+        let descriptor = device.device_descriptor()?;
+        if descriptor.vendor_id() != CORSAIR_VENDOR_ID {
+            continue;
+        }
+        let model = match Model::from_product_id(descriptor.product_id()) {
+            Some(model) => model,
+            None => continue,
+        };
+
+        let (product_name, serial_number) = read_strings(&device, &descriptor);
+        let info = DeviceInfo {
+            bus: device.bus_number(),
+            address: device.address(),
+            port_path: port_path(device.bus_number(), device.address()),
+            vendor_id: descriptor.vendor_id(),
+            product_id: descriptor.product_id(),
+            model,
+            product_name,
+            serial_number,
+        };
+        found.push((device, info));
+    }
+
+    Ok(found)
+}
+
+/// Lists every supported Corsair Link device attached to the system.
+pub fn devices(context: &libusb::Context) -> Result<Vec<DeviceInfo>> {
+    Ok(enumerate(context)?.into_iter().map(|(_, info)| info).collect())
+}
+
 pub struct Device<'a> {
     dev: libusb::DeviceHandle<'a>,
     read_timeout: Duration,
@@ -28,63 +141,79 @@ impl<'a> fmt::Debug for Device<'a> {
 }
 
 impl<'a> Device<'a> {
+    /// Opens the first device with the given vendor and product IDs.
     pub fn open(context: &'a libusb::Context, vendor_id: u16, product_id: u16) -> Result<Device<'a>> {
-        for mut device in context.devices().unwrap().iter() {
-            let device_desc = device.device_descriptor().unwrap();
+        for device in context.devices()?.iter() {
+            let device_desc = device.device_descriptor()?;
 
             if device_desc.vendor_id() == vendor_id && device_desc.product_id() == product_id {
-                let mut handle = device.open().unwrap();
-                if handle.kernel_driver_active(INTERFACE_NUMBER)? {
-                    handle.detach_kernel_driver(INTERFACE_NUMBER)?;
-                }
-                handle.claim_interface(INTERFACE_NUMBER)?;
-
-                return Ok(Device {
-                    dev: handle,
-                    read_timeout: Duration::from_millis(DEFAULT_READ_TIMEOUT),
-                    write_timeout: Duration::from_millis(DEFAULT_WRITE_TIMEOUT),
-                })
+                return Device::open_device(&device);
             }
         };
 
-        Err("No device found".into())
+        Err(TransportError::NoDevice.into())
+    }
+
+    /// Opens the supported device picked out by `selector`, returning it along
+    /// with its description.
+    pub fn open_selected(context: &'a libusb::Context, selector: &Selector) -> Result<(Device<'a>, DeviceInfo)> {
+        for (index, (device, info)) in enumerate(context)?.into_iter().enumerate() {
+            if selector.matches(index, &info) {
+                return Ok((Device::open_device(&device)?, info));
+            }
+        }
+
+        Err(TransportError::NoDevice.into())
     }
 
-    fn write(&self, data: &[u8]) -> Result<usize> {
+    fn open_device(device: &libusb::Device<'a>) -> Result<Device<'a>> {
+        let mut handle = device.open()?;
+        if handle.kernel_driver_active(INTERFACE_NUMBER)? {
+            handle.detach_kernel_driver(INTERFACE_NUMBER)?;
+        }
+        handle.claim_interface(INTERFACE_NUMBER)?;
+
+        Ok(Device {
+            dev: handle,
+            read_timeout: Duration::from_millis(DEFAULT_READ_TIMEOUT),
+            write_timeout: Duration::from_millis(DEFAULT_WRITE_TIMEOUT),
+        })
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        self.write_timeout = timeout;
+    }
+}
+
+impl<'a> Backend for Device<'a> {
+    fn write_report(&mut self, data: &[u8], timeout: Duration) -> Result<usize> {
         self.dev.write_control(
             libusb::request_type(libusb::Direction::Out, libusb::RequestType::Class, libusb::Recipient::Interface),
             HID_SET_REPORT, // 0x09
             HID_REPORT_TYPE_OUTPUT << 8 | HID_REPORT_NUMBER,
             INTERFACE_NUMBER as u16,
             data,
-            self.write_timeout,
-        ).chain_err(|| "Error writing to USB device")
+            timeout,
+        ).map_err(|e| TransportError::Usb(e).into())
     }
 
-    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+    fn read_report(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
         self.dev.read_interrupt(
             INTERRUPT_IN_ENDPOINT,
             buf,
-            self.read_timeout
-        ).chain_err(|| "Error reading from USB device")
+            timeout
+        ).map_err(|e| TransportError::Usb(e).into())
     }
 
-    pub fn write_packet<R: protocol::Register, V: protocol::Value<R>>(&self, packet: protocol::TxPacket<R,V>) -> Result<protocol::RxPacket<R, V>> {
-        let encoded = packet.encode().unwrap();
-        println!("Writing packet: {:x}", encoded.as_hex());
-        self.write(&encoded[..])?;
-
-        let mut buf: Vec<u8> = vec![0u8; protocol::PACKET_SIZE];
-        self.read(buf.as_mut_slice())?;
-        println!("Received response: {:x}", buf.as_hex());
-        if buf[0] != encoded[1] {
-            self.read(buf.as_mut_slice())?;
-            println!("Received response: {:x}", buf.as_hex());
-            if buf[0] != encoded[1] {
-                self.read(buf.as_mut_slice())?;
-                println!("Received response: {:x}", buf.as_hex());
-            }
-        }
-        protocol::RxPacket::decode(packet, &buf[..])
+    fn read_timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    fn write_timeout(&self) -> Duration {
+        self.write_timeout
     }
 }
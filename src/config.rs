@@ -0,0 +1,497 @@
+//! # Configuration files
+//!
+//! A TOML file declaring the state devices should be in: LED mode, colors and
+//! temperature gradient, and the profile, fixed speed or curve of each fan and
+//! the pump. Applying a configuration polls the device, compares it with the
+//! file, and writes only the settings that differ.
+//!
+//! ```toml
+//! [[device]]
+//! port = "1-4.2"
+//!
+//! [device.led]
+//! mode = { FourColorCycle = 7 }
+//! colors = ["ff0000", "00ff00", "0000ff"]
+//! gradient = [
+//!     { temperature = 30, color = "00ff00" },
+//!     { temperature = 40, color = "ffff00" },
+//!     { temperature = 50, color = "ff0000" },
+//! ]
+//!
+//! [[device.fan]]
+//! fan = 0
+//! pwm = 40
+//!
+//! [[device.fan]]
+//! fan = 1
+//! curve = [
+//!     { temperature = 25, rpm = 600 },
+//!     { temperature = 30, rpm = 800 },
+//!     { temperature = 35, rpm = 1200 },
+//!     { temperature = 40, rpm = 1600 },
+//!     { temperature = 45, rpm = 2000 },
+//! ]
+//!
+//! [device.pump]
+//! profile = "ProfilePerformance"
+//! ```
+//!
+//! A device is chosen by its USB `port`, or its `index` in enumeration order,
+//! defaulting to the first Corsair Link HID cooler. The LED settings apply to
+//! the first LED, and the pump is the cooler's last fan channel, which the
+//! Commander Mini doesn't have. Each fan takes exactly one of `profile`,
+//! `pwm` (a percentage), `rpm` or `curve`.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use toml;
+
+use errors::*;
+use backends::Backend;
+use backends::usbhid::Selector;
+use devices::Model;
+use devices::cooler::h110i::{Device, FanCurve, FanProfile, LedMode, RgbColor, Temperature, TemperatureGradient};
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(rename = "device", default)]
+    pub devices: Vec<DeviceConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub port: Option<String>,
+    pub index: Option<usize>,
+    pub led: Option<LedConfig>,
+    #[serde(rename = "fan", default)]
+    pub fans: Vec<FanConfig>,
+    pub pump: Option<FanConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LedConfig {
+    pub mode: Option<LedMode>,
+    /// Up to four colors to cycle between, as `RRGGBB`.
+    pub colors: Option<Vec<String>>,
+    pub gradient: Option<Vec<GradientPoint>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GradientPoint {
+    pub temperature: Temperature,
+    pub color: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FanConfig {
+    /// The fan number, which must be left out for the pump.
+    pub fan: Option<u8>,
+    pub profile: Option<FanProfile>,
+    pub pwm: Option<u8>,
+    pub rpm: Option<u16>,
+    pub curve: Option<Vec<CurvePoint>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CurvePoint {
+    pub temperature: Temperature,
+    pub rpm: u16,
+}
+
+/// What a fan is doing: running a firmware profile, or one of the profiles
+/// that take a setting of their own.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FanSetting {
+    Profile(FanProfile),
+    Pwm(u8),
+    Rpm(u16),
+    Curve(FanCurve),
+}
+
+impl FanSetting {
+    pub fn profile(&self) -> FanProfile {
+        match *self {
+            FanSetting::Profile(profile) => profile,
+            FanSetting::Pwm(_) => FanProfile::FixedPWM,
+            FanSetting::Rpm(_) => FanProfile::FixedRPM,
+            FanSetting::Curve(_) => FanProfile::Custom,
+        }
+    }
+
+    /// Returns true if a fan in the `current` state already satisfies this
+    /// setting. A bare profile is satisfied by any setting of that profile.
+    fn is_satisfied_by(&self, current: &FanSetting) -> bool {
+        match *self {
+            FanSetting::Profile(profile) => current.profile() == profile,
+            _ => self == current,
+        }
+    }
+}
+
+impl fmt::Display for FanSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FanSetting::Profile(profile) => write!(f, "{:?}", profile),
+            FanSetting::Pwm(percent) => write!(f, "{}% PWM", percent),
+            FanSetting::Rpm(rpm) => write!(f, "{} RPM", rpm),
+            FanSetting::Curve(ref curve) => {
+                let points: Vec<String> = curve.points().iter()
+                    .map(|&(temperature, rpm)| format!("{}:{}", temperature.degrees_c(), rpm))
+                    .collect();
+                write!(f, "curve {}", points.join(","))
+            },
+        }
+    }
+}
+
+/// A single write needed to bring a device in line with its configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    LedColors { from: [RgbColor; 4], to: [RgbColor; 4] },
+    LedGradient { from: TemperatureGradient, to: TemperatureGradient },
+    LedMode { from: LedMode, to: LedMode },
+    Fan { fan: u8, from: FanSetting, to: FanSetting },
+}
+
+impl Change {
+    pub fn apply<B: Backend>(&self, device: &mut Device<B>) -> Result<()> {
+        match *self {
+            Change::LedColors { to, .. } => device.set_led_colors(0, to),
+            Change::LedGradient { ref to, .. } => device.set_led_gradient(0, to),
+            Change::LedMode { to, .. } => device.set_led_mode(to),
+            Change::Fan { fan, to, .. } => match to {
+                FanSetting::Profile(profile) => device.set_fan_mode(fan, profile),
+                FanSetting::Pwm(percent) => device.set_fan_pwm(fan, percent),
+                FanSetting::Rpm(rpm) => device.set_fan_rpm(fan, rpm),
+                FanSetting::Curve(ref curve) => device.set_fan_curve(fan, curve),
+            },
+        }
+    }
+}
+
+fn format_colors(colors: &[RgbColor]) -> String {
+    colors.iter().map(|color| color.to_string()).collect::<Vec<String>>().join(",")
+}
+
+fn format_gradient(gradient: &TemperatureGradient) -> String {
+    gradient.points().iter()
+        .map(|&(temperature, color)| format!("{}:{}", temperature.degrees_c(), color))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::LedColors { ref from, ref to } =>
+                write!(f, "LED colors: {} -> {}", format_colors(from), format_colors(to)),
+            Change::LedGradient { ref from, ref to } =>
+                write!(f, "LED gradient: {} -> {}", format_gradient(from), format_gradient(to)),
+            Change::LedMode { from, to } => write!(f, "LED mode: {:?} -> {:?}", from, to),
+            Change::Fan { fan, ref from, ref to } => write!(f, "Fan {}: {} -> {}", fan, from, to),
+        }
+    }
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        contents.parse().map_err(|e| match e {
+            Error::Config(message) => Error::Config(format!("{}: {}", path.display(), message)),
+            e => e,
+        })
+    }
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Config> {
+        toml::from_str(s).map_err(|e| Error::Config(e.to_string()))
+    }
+}
+
+impl LedConfig {
+    fn colors(&self) -> Result<Option<[RgbColor; 4]>> {
+        let colors = match self.colors {
+            Some(ref colors) => colors.iter().map(|color| color.parse()).collect::<Result<Vec<RgbColor>>>()?,
+            None => return Ok(None),
+        };
+        if colors.is_empty() || colors.len() > 4 {
+            return Err(Error::invalid_argument("colors", "from one to four colors can be cycled"));
+        }
+        let n = colors.len();
+        Ok(Some([colors[0], colors[1 % n], colors[2 % n], colors[3 % n]]))
+    }
+
+    fn gradient(&self) -> Result<Option<TemperatureGradient>> {
+        let points = match self.gradient {
+            Some(ref points) => points.iter()
+                .map(|point| Ok((point.temperature, point.color.parse()?)))
+                .collect::<Result<Vec<(Temperature, RgbColor)>>>()?,
+            None => return Ok(None),
+        };
+        if points.len() != 3 {
+            return Err(Error::invalid_argument("gradient", "exactly three points are needed"));
+        }
+        TemperatureGradient::new([points[0], points[1], points[2]]).map(Some)
+    }
+}
+
+impl FanConfig {
+    fn setting(&self) -> Result<FanSetting> {
+        let mut settings = Vec::new();
+        if let Some(profile) = self.profile {
+            settings.push(FanSetting::Profile(profile));
+        }
+        if let Some(percent) = self.pwm {
+            settings.push(FanSetting::Pwm(percent));
+        }
+        if let Some(rpm) = self.rpm {
+            settings.push(FanSetting::Rpm(rpm));
+        }
+        if let Some(ref points) = self.curve {
+            if points.len() != 5 {
+                return Err(Error::invalid_argument("fan curve", "exactly five points are needed"));
+            }
+            let mut curve = [(Temperature::from(0), 0); 5];
+            for (i, point) in points.iter().enumerate() {
+                curve[i] = (point.temperature, point.rpm);
+            }
+            settings.push(FanSetting::Curve(FanCurve::new(curve)?));
+        }
+
+        match settings.len() {
+            1 => Ok(settings[0]),
+            0 => Err(Error::Config("a fan needs a profile, pwm, rpm or curve".to_string())),
+            _ => Err(Error::Config("a fan takes only one of profile, pwm, rpm and curve".to_string())),
+        }
+    }
+}
+
+/// Reads what a fan is currently doing, including the setting of its profile
+/// if it has one.
+fn fan_setting<B: Backend>(device: &mut Device<B>, fan: u8, profile: FanProfile) -> Result<FanSetting> {
+    Ok(match profile {
+        FanProfile::FixedPWM => FanSetting::Pwm(device.get_fan_pwm(fan)?),
+        FanProfile::FixedRPM => FanSetting::Rpm(device.get_fan_rpm(fan)?),
+        FanProfile::Custom => FanSetting::Curve(device.get_fan_curve(fan)?),
+        profile => FanSetting::Profile(profile),
+    })
+}
+
+impl DeviceConfig {
+    /// Returns the selector for the device this entry configures.
+    pub fn selector(&self) -> Selector {
+        match (self.port.as_ref(), self.index) {
+            (Some(port), _) => Selector::PortPath(port.clone()),
+            (None, Some(index)) => Selector::Index(index),
+            (None, None) => Selector::Model(Model::LinkHid),
+        }
+    }
+
+    fn fan_settings(&self, pump_channel: Option<u8>) -> Result<Vec<(u8, FanSetting)>> {
+        let mut settings = Vec::new();
+        for config in &self.fans {
+            match config.fan {
+                Some(fan) => settings.push((fan, config.setting()?)),
+                None => return Err(Error::Config("every [[device.fan]] needs a fan number".to_string())),
+            }
+        }
+        if let Some(ref pump) = self.pump {
+            if pump.fan.is_some() {
+                return Err(Error::Config("the pump doesn't take a fan number".to_string()));
+            }
+            match pump_channel {
+                Some(channel) => settings.push((channel, pump.setting()?)),
+                None => return Err(Error::invalid_argument("pump", "the device has no pump")),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Polls `device`, which must have had its metadata read, and returns the
+    /// writes needed to bring it in line with this configuration, in the
+    /// order they should be made. Colors are written before the LED mode so
+    /// that the LED never shows stale colors in its new mode.
+    pub fn diff<B: Backend>(&self, device: &mut Device<B>) -> Result<Vec<Change>> {
+        let mut changes = Vec::new();
+
+        if let Some(ref led) = self.led {
+            let colors = led.colors()?;
+            let gradient = led.gradient()?;
+            if device.led_count() == 0 {
+                return Err(Error::invalid_argument("led", "the device has no LEDs"));
+            }
+            device.poll_leds()?;
+
+            if let Some(to) = colors {
+                let from = device.led_cycle_colors[0];
+                if from != to {
+                    changes.push(Change::LedColors { from, to });
+                }
+            }
+            if let Some(to) = gradient {
+                let from = device.get_led_gradient(0)?;
+                if from != to {
+                    changes.push(Change::LedGradient { from, to });
+                }
+            }
+            if let Some(to) = led.mode {
+                let from = device.led_modes[0];
+                if from != to {
+                    changes.push(Change::LedMode { from, to });
+                }
+            }
+        }
+
+        let fans = self.fan_settings(device.pump_channel())?;
+        if !fans.is_empty() {
+            device.poll_fans()?;
+        }
+        for (fan, to) in fans {
+            let profile = match device.fan_modes.get(fan as usize) {
+                Some(mode) => mode.profile(),
+                None => return Err(Error::invalid_argument("fan", format!("fan {} does not exist; the device has {}", fan, device.fan_count()))),
+            };
+            let from = fan_setting(device, fan, profile)?;
+            if !to.is_satisfied_by(&from) {
+                changes.push(Change::Fan { fan, from, to });
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Brings `device` in line with this configuration, returning the changes
+    /// that were made.
+    pub fn apply<B: Backend>(&self, device: &mut Device<B>) -> Result<Vec<Change>> {
+        let changes = self.diff(device)?;
+        for change in &changes {
+            info!("Applying {}", change);
+            change.apply(device)?;
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backends::simulator::Simulator;
+    use devices::cooler::h110i::CoolerModel;
+
+    const CONFIG: &str = r#"
+        [[device]]
+        port = "1-4.2"
+
+        [device.led]
+        mode = "Static"
+        colors = ["ff0000", "00ff00"]
+
+        [[device.fan]]
+        fan = 0
+        pwm = 40
+
+        [[device.fan]]
+        fan = 1
+        pwm = 50
+
+        [device.pump]
+        profile = "ProfileDefault"
+    "#;
+
+    fn cooler(model: CoolerModel) -> Device<Simulator> {
+        let mut cooler = Device::new(Simulator::for_model(model));
+        cooler.get_metadata().unwrap();
+        cooler
+    }
+
+    fn device_config(toml: &str) -> DeviceConfig {
+        let mut config: Config = toml.parse().unwrap();
+        assert_eq!(config.devices.len(), 1);
+        config.devices.remove(0)
+    }
+
+    #[test]
+    fn parses_toml() {
+        let config = device_config(CONFIG);
+        assert_eq!(config.selector(), Selector::PortPath("1-4.2".to_string()));
+
+        let led = config.led.as_ref().unwrap();
+        assert_eq!(led.mode, Some(LedMode::Static));
+        assert_eq!(led.colors().unwrap(), Some([RgbColor(0xff, 0, 0), RgbColor(0, 0xff, 0), RgbColor(0xff, 0, 0), RgbColor(0, 0xff, 0)]));
+
+        assert_eq!(config.fan_settings(Some(2)).unwrap(), vec![
+            (0, FanSetting::Pwm(40)),
+            (1, FanSetting::Pwm(50)),
+            (2, FanSetting::Profile(FanProfile::ProfileDefault)),
+        ]);
+    }
+
+    #[test]
+    fn rejects_invalid_toml() {
+        assert!(matches!("[[device]]\nspeed = 3".parse::<Config>(), Err(Error::Config(_))));
+
+        let config = device_config("[[device]]\n[[device.fan]]\nfan = 0\npwm = 40\nrpm = 1000");
+        assert!(matches!(config.fan_settings(None), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn diff_only_includes_differing_settings() {
+        let mut cooler = cooler(CoolerModel::H110i);
+        // Fan 1 is already running at 50%
+        cooler.backend_mut().fans[1].mode = 0x83;
+
+        let changes = device_config(CONFIG).diff(&mut cooler).unwrap();
+        let black = RgbColor(0, 0, 0);
+        assert_eq!(changes, vec![
+            Change::LedColors {
+                from: [black; 4],
+                to: [RgbColor(0xff, 0, 0), RgbColor(0, 0xff, 0), RgbColor(0xff, 0, 0), RgbColor(0, 0xff, 0)],
+            },
+            Change::Fan { fan: 0, from: FanSetting::Profile(FanProfile::ProfileDefault), to: FanSetting::Pwm(40) },
+        ]);
+    }
+
+    #[test]
+    fn apply_writes_changes_once() {
+        let mut cooler = cooler(CoolerModel::H110i);
+        let config = device_config(CONFIG);
+
+        assert_eq!(config.apply(&mut cooler).unwrap().len(), 3);
+        let simulator = cooler.backend();
+        assert_eq!(simulator.leds[0].cycle_colors, [0xff, 0, 0, 0, 0xff, 0, 0xff, 0, 0, 0, 0xff, 0]);
+        assert_eq!((simulator.fans[0].mode & 0x0e, simulator.fans[0].fixed_pwm), (FanProfile::FixedPWM as u8, 102));
+        assert_eq!((simulator.fans[1].mode & 0x0e, simulator.fans[1].fixed_pwm), (FanProfile::FixedPWM as u8, 128));
+        assert_eq!(simulator.fans[2].mode & 0x0e, FanProfile::ProfileDefault as u8);
+
+        assert_eq!(config.apply(&mut cooler).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn bare_profile_is_satisfied_by_any_setting_of_it() {
+        let mut cooler = cooler(CoolerModel::H110i);
+        cooler.set_fan_rpm(0, 1500).unwrap();
+
+        let config = device_config("[[device]]\n[[device.fan]]\nfan = 0\nprofile = \"FixedRPM\"");
+        assert_eq!(config.diff(&mut cooler).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn pump_requires_a_device_with_one() {
+        let mut cooler = cooler(CoolerModel::CommanderMini);
+        let config = device_config("[[device]]\n[device.pump]\nprofile = \"ProfileQuiet\"");
+        assert!(matches!(config.diff(&mut cooler), Err(Error::InvalidArgument { .. })));
+    }
+}
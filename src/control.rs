@@ -0,0 +1,418 @@
+//! # Host-side fan control
+//!
+//! A control loop that runs on the host instead of in the cooler's firmware.
+//! Each controlled fan follows a temperature input through a user-defined
+//! curve, with exponential smoothing of the input and hysteresis on the way
+//! down so that fans don't hunt around a threshold. Fans are driven with the
+//! `FixedPWM` or `FixedRPM` profiles.
+//!
+//! A fixed speed set by a controller that has stopped could leave the fans
+//! slow while temperatures climb. So whenever a `Controller` stops, whether
+//! it is dropped, an input fails, or the device rejects a speed, every fan it
+//! drives is first put back on a firmware profile.
+
+use std::cmp::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use errors::*;
+use backends::Backend;
+use devices::cooler::h110i::{Device, FanProfile};
+use sensors::TemperatureSource;
+
+/// A piecewise linear mapping from temperature in degrees Celsius to fan
+/// output. Below the first point and above the last the output is held at
+/// the nearest point's value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve {
+    points: Vec<(f64, f64)>,
+}
+
+impl Curve {
+    /// Creates a curve from `(temperature, output)` points, which must be
+    /// given in order of strictly increasing temperature.
+    pub fn new(points: Vec<(f64, f64)>) -> Result<Curve> {
+        if points.is_empty() {
+            return Err(Error::invalid_argument("curve", "a curve needs at least one point"));
+        }
+        for pair in points.windows(2) {
+            if pair[1].0.partial_cmp(&pair[0].0) != Some(Ordering::Greater) {
+                return Err(Error::invalid_argument("curve", format!("temperature {}°C does not come after {}°C", pair[1].0, pair[0].0)));
+            }
+        }
+        Ok(Curve { points })
+    }
+
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+
+    pub fn evaluate(&self, temperature: f64) -> f64 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if temperature <= first.0 {
+            return first.1;
+        }
+        if temperature >= last.0 {
+            return last.1;
+        }
+
+        for pair in self.points.windows(2) {
+            let ((t0, out0), (t1, out1)) = (pair[0], pair[1]);
+            if temperature <= t1 {
+                return out0 + (out1 - out0) * (temperature - t0) / (t1 - t0);
+            }
+        }
+        last.1
+    }
+}
+
+/// Where a controlled fan gets its temperature from.
+pub enum Input {
+    /// One of the cooler's own temperature sensors, by index.
+    Cooler(u8),
+    /// Any other source, such as a hwmon sensor or a command.
+    Source(Box<dyn TemperatureSource>),
+}
+
+/// How a curve's output is applied to a fan.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Output {
+    /// The curve gives a duty cycle as a percentage.
+    Pwm,
+    /// The curve gives a speed in RPM.
+    Rpm,
+}
+
+pub struct FanControl {
+    fan: u8,
+    input: Input,
+    curve: Curve,
+    output: Output,
+    hysteresis: f64,
+    smoothing: f64,
+
+    smoothed: Option<f64>,
+    applied: Option<(f64, f64)>,
+}
+
+impl FanControl {
+    /// Controls `fan` by applying `curve` to `input`, with no smoothing and no
+    /// hysteresis.
+    pub fn new(fan: u8, input: Input, curve: Curve, output: Output) -> FanControl {
+        FanControl {
+            fan,
+            input,
+            curve,
+            output,
+            hysteresis: 0.0,
+            smoothing: 1.0,
+
+            smoothed: None,
+            applied: None,
+        }
+    }
+
+    /// Sets how far, in degrees Celsius, the temperature must fall below the
+    /// point where the output was last changed before the output is lowered.
+    /// Rising temperatures always raise the output immediately.
+    pub fn hysteresis(mut self, degrees: f64) -> FanControl {
+        self.hysteresis = degrees.max(0.0);
+        self
+    }
+
+    /// Sets the weight given to each new reading in an exponential moving
+    /// average of the input, from 1.0 (no smoothing) down towards 0.0.
+    pub fn smoothing(mut self, weight: f64) -> FanControl {
+        self.smoothing = weight.clamp(0.01, 1.0);
+        self
+    }
+
+    pub fn fan(&self) -> u8 {
+        self.fan
+    }
+
+    /// Returns the smoothed temperature and the output last applied to the
+    /// fan, if any.
+    pub fn applied(&self) -> Option<(f64, f64)> {
+        self.applied
+    }
+
+    /// Feeds a new reading in, returning the output to apply if it should
+    /// change.
+    fn update(&mut self, reading: f64) -> Option<f64> {
+        let temperature = match self.smoothed {
+            Some(smoothed) => smoothed + self.smoothing * (reading - smoothed),
+            None => reading,
+        };
+        self.smoothed = Some(temperature);
+
+        let target = self.curve.evaluate(temperature);
+        let change = match self.applied {
+            None => true,
+            Some((_, output)) if target > output => true,
+            Some((at, output)) => target < output && temperature <= at - self.hysteresis,
+        };
+
+        if change {
+            self.applied = Some((temperature, target));
+            Some(target)
+        } else {
+            None
+        }
+    }
+}
+
+/// Drives a set of fans from host-side curves. Dropping the controller hands
+/// the fans back to the fallback firmware profile.
+pub struct Controller<B: Backend> {
+    device: Device<B>,
+    fans: Vec<FanControl>,
+    fallback: FanProfile,
+    /// Set once every fan is back on the fallback profile, until a step
+    /// takes control of one again.
+    restored: bool,
+}
+
+impl<B: Backend> Controller<B> {
+    /// Takes control of `device`, which must have had its metadata read. Fans
+    /// fall back to `FanProfile::ProfileDefault` unless told otherwise.
+    pub fn new(device: Device<B>) -> Controller<B> {
+        Controller { device, fans: Vec::new(), fallback: FanProfile::ProfileDefault, restored: false }
+    }
+
+    pub fn fallback(mut self, profile: FanProfile) -> Controller<B> {
+        self.fallback = profile;
+        self
+    }
+
+    pub fn control(mut self, fan: FanControl) -> Controller<B> {
+        self.fans.push(fan);
+        self
+    }
+
+    pub fn device(&self) -> &Device<B> {
+        &self.device
+    }
+
+    pub fn device_mut(&mut self) -> &mut Device<B> {
+        &mut self.device
+    }
+
+    pub fn fans(&self) -> &[FanControl] {
+        &self.fans
+    }
+
+    fn read_input(device: &mut Device<B>, input: &mut Input) -> Result<f64> {
+        match *input {
+            Input::Cooler(sensor) => {
+                device.poll_temperatures()?;
+                match device.temperatures.get(sensor as usize) {
+                    Some(temperature) => Ok(temperature.degrees_c()),
+                    None => Err(Error::invalid_argument("sensor", format!("temperature sensor {} does not exist", sensor))),
+                }
+            },
+            Input::Source(ref mut source) => source.read_degrees_c(),
+        }
+    }
+
+    fn try_step(&mut self) -> Result<()> {
+        for control in &mut self.fans {
+            let reading = Controller::read_input(&mut self.device, &mut control.input)?;
+            if let Some(output) = control.update(reading) {
+                self.restored = false;
+                match control.output {
+                    Output::Pwm => self.device.set_fan_pwm(control.fan, output.clamp(0.0, 100.0).round() as u8)?,
+                    Output::Rpm => self.device.set_fan_rpm(control.fan, output.clamp(0.0, 65535.0).round() as u16)?,
+                }
+                debug!("Fan {} at {:.1}°C set to {:.0} {}", control.fan, reading, output,
+                       if control.output == Output::Pwm { "%" } else { "RPM" });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads every input once and updates the fans whose output changed. On
+    /// failure, the fans are handed back to the fallback profile before the
+    /// error is returned.
+    pub fn step(&mut self) -> Result<()> {
+        match self.try_step() {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                error!("Fan control failed, falling back to {:?}: {}", self.fallback, e);
+                self.restore();
+                Err(e)
+            },
+        }
+    }
+
+    /// Steps the controller every `interval` for as long as `keep_running`
+    /// returns true, then hands the fans back to the fallback profile.
+    pub fn run<F: FnMut() -> bool>(&mut self, interval: Duration, mut keep_running: F) -> Result<()> {
+        while keep_running() {
+            self.step()?;
+            thread::sleep(interval);
+        }
+        self.restore();
+        Ok(())
+    }
+
+    /// Puts every controlled fan on the fallback profile. Errors are logged
+    /// rather than returned, so that one failing fan doesn't stop the others
+    /// from being restored. Once every fan has been restored, further calls do
+    /// nothing until a step sets a fan's speed again.
+    pub fn restore(&mut self) {
+        if self.restored {
+            return;
+        }
+
+        let mut restored = true;
+        for control in &mut self.fans {
+            control.applied = None;
+            if let Err(e) = self.device.set_fan_mode(control.fan, self.fallback) {
+                warn!("Failed to restore fan {} to {:?}: {}", control.fan, self.fallback, e);
+                restored = false;
+            }
+        }
+        self.restored = restored;
+    }
+}
+
+impl<B: Backend> Drop for Controller<B> {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use backends::simulator::Simulator;
+
+    /// A temperature source that plays back a list of readings, then fails.
+    struct Readings(VecDeque<f64>);
+
+    impl TemperatureSource for Readings {
+        fn read_degrees_c(&mut self) -> Result<f64> {
+            self.0.pop_front().ok_or_else(|| Error::Sensor("no more readings".to_string()))
+        }
+    }
+
+    /// A simulator that is still reachable once the controller owning it has
+    /// been dropped.
+    #[derive(Clone)]
+    struct Shared(Rc<RefCell<Simulator>>);
+
+    impl Backend for Shared {
+        fn write_report(&mut self, data: &[u8], timeout: Duration) -> Result<usize> {
+            self.0.borrow_mut().write_report(data, timeout)
+        }
+
+        fn read_report(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+            self.0.borrow_mut().read_report(buf, timeout)
+        }
+    }
+
+    const FALLBACK: FanProfile = FanProfile::ProfileQuiet;
+
+    fn controller(readings: &[f64], hysteresis: f64, smoothing: f64) -> (Controller<Shared>, Shared) {
+        let simulator = Shared(Rc::new(RefCell::new(Simulator::new())));
+        let mut device = Device::new(simulator.clone());
+        device.get_metadata().unwrap();
+
+        let curve = Curve::new(vec![(30.0, 20.0), (50.0, 100.0)]).unwrap();
+        let input = Input::Source(Box::new(Readings(readings.iter().cloned().collect())));
+        let fan = FanControl::new(0, input, curve, Output::Pwm).hysteresis(hysteresis).smoothing(smoothing);
+        (Controller::new(device).fallback(FALLBACK).control(fan), simulator)
+    }
+
+    fn fan_profile(simulator: &Shared) -> u8 {
+        simulator.0.borrow().fans[0].mode & 0x0e
+    }
+
+    fn fixed_pwm(simulator: &Shared) -> u8 {
+        simulator.0.borrow().fans[0].fixed_pwm
+    }
+
+    #[test]
+    fn curve_interpolates_and_clamps() {
+        let curve = Curve::new(vec![(30.0, 20.0), (50.0, 100.0)]).unwrap();
+        assert_eq!(curve.evaluate(20.0), 20.0);
+        assert_eq!(curve.evaluate(40.0), 60.0);
+        assert_eq!(curve.evaluate(60.0), 100.0);
+        assert!(Curve::new(vec![(30.0, 20.0), (30.0, 100.0)]).is_err());
+    }
+
+    #[test]
+    fn hysteresis_delays_lowering_the_output() {
+        let (mut controller, simulator) = controller(&[40.0, 45.0, 42.0, 39.0], 5.0, 1.0);
+
+        controller.step().unwrap();
+        assert_eq!(controller.fans()[0].applied(), Some((40.0, 60.0)));
+        assert_eq!(fan_profile(&simulator), FanProfile::FixedPWM as u8);
+        assert_eq!(fixed_pwm(&simulator), 153);
+
+        controller.step().unwrap();
+        assert_eq!(controller.fans()[0].applied(), Some((45.0, 80.0)));
+
+        // Within 5°C of where the output was raised, so it is held
+        controller.step().unwrap();
+        assert_eq!(controller.fans()[0].applied(), Some((45.0, 80.0)));
+        assert_eq!(fixed_pwm(&simulator), 204);
+
+        controller.step().unwrap();
+        assert_eq!(controller.fans()[0].applied(), Some((39.0, 56.0)));
+        assert_eq!(fixed_pwm(&simulator), 143);
+    }
+
+    #[test]
+    fn smoothing_averages_readings() {
+        let (mut controller, _simulator) = controller(&[30.0, 50.0, 50.0], 0.0, 0.5);
+
+        controller.step().unwrap();
+        assert_eq!(controller.fans()[0].applied(), Some((30.0, 20.0)));
+        controller.step().unwrap();
+        assert_eq!(controller.fans()[0].applied(), Some((40.0, 60.0)));
+        controller.step().unwrap();
+        assert_eq!(controller.fans()[0].applied(), Some((45.0, 80.0)));
+    }
+
+    #[test]
+    fn source_error_falls_back_to_firmware_profile() {
+        let (mut controller, simulator) = controller(&[40.0], 0.0, 1.0);
+        controller.step().unwrap();
+        assert_eq!(fan_profile(&simulator), FanProfile::FixedPWM as u8);
+
+        assert!(matches!(controller.step(), Err(Error::Sensor(_))));
+        assert_eq!(fan_profile(&simulator), FALLBACK as u8);
+        assert_eq!(controller.fans()[0].applied(), None);
+    }
+
+    #[test]
+    fn restore_only_writes_once() {
+        let (mut controller, simulator) = controller(&[40.0], 0.0, 1.0);
+        controller.step().unwrap();
+        assert!(controller.step().is_err());
+        assert_eq!(fan_profile(&simulator), FALLBACK as u8);
+
+        // Anything the fans are changed to after the controller has given
+        // them back is left alone, including when it is dropped
+        simulator.0.borrow_mut().fans[0].mode = 0x82;
+        controller.restore();
+        drop(controller);
+        assert_eq!(simulator.0.borrow().fans[0].mode, 0x82);
+    }
+
+    #[test]
+    fn drop_falls_back_to_firmware_profile() {
+        let (mut controller, simulator) = controller(&[40.0], 0.0, 1.0);
+        controller.step().unwrap();
+        assert_eq!(fan_profile(&simulator), FanProfile::FixedPWM as u8);
+
+        drop(controller);
+        assert_eq!(fan_profile(&simulator), FALLBACK as u8);
+    }
+}
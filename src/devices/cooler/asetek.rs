@@ -0,0 +1,138 @@
+//! # Asetek-based Corsair coolers
+//!
+//! The H80i GT, H100i GTX, H110i GT, H80i v2, H100i v2 and H115i, which speak
+//! the bulk-transfer protocol in `protocol::asetek`. Every setting is a single
+//! command that the cooler answers with its status, so each setter returns
+//! that status. Settings can't be read back, and the cooler forgets them when
+//! it loses power.
+
+use libusb;
+
+use errors::*;
+use backends::Backend;
+use backends::usbbulk;
+use backends::usbhid::Selector;
+use devices::ASETEK_MODELS;
+use devices::cooler::h110i::RgbColor;
+use protocol::asetek::{self, Request, CURVE_POINTS};
+
+pub use protocol::asetek::{PumpMode, Status};
+
+/// The highest temperature, in degrees Celsius, accepted in a fan curve or as
+/// the alert temperature.
+pub const MAX_TEMPERATURE: u8 = 100;
+
+/// The points of a fan curve, as `(temperature, duty)` pairs in degrees
+/// Celsius and percent. Between points the firmware interpolates the duty
+/// cycle from the liquid temperature.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FanCurve {
+    temperatures: [u8; CURVE_POINTS],
+    duties: [u8; CURVE_POINTS],
+}
+
+impl FanCurve {
+    /// Creates a curve from `(temperature, duty)` points, which must be given
+    /// in order of strictly increasing temperature, with duty cycles that
+    /// never decrease.
+    pub fn new(points: [(u8, u8); CURVE_POINTS]) -> Result<FanCurve> {
+        for &(temperature, duty) in &points {
+            if temperature > MAX_TEMPERATURE {
+                return Err(Error::invalid_argument("fan curve", format!("{}°C is more than {}°C", temperature, MAX_TEMPERATURE)));
+            }
+            if duty > 100 {
+                return Err(Error::invalid_argument("fan curve", format!("{}% is more than 100%", duty)));
+            }
+        }
+        for pair in points.windows(2) {
+            let ((t0, duty0), (t1, duty1)) = (pair[0], pair[1]);
+            if t1 <= t0 {
+                return Err(Error::invalid_argument("fan curve", format!("temperature {}°C does not come after {}°C", t1, t0)));
+            }
+            if duty1 < duty0 {
+                return Err(Error::invalid_argument("fan curve", format!("{}% at {}°C is less than {}% at {}°C", duty1, t1, duty0, t0)));
+            }
+        }
+
+        let mut curve = FanCurve { temperatures: [0; CURVE_POINTS], duties: [0; CURVE_POINTS] };
+        for (i, &(temperature, duty)) in points.iter().enumerate() {
+            curve.temperatures[i] = temperature;
+            curve.duties[i] = duty;
+        }
+        Ok(curve)
+    }
+
+    pub fn points(&self) -> Vec<(u8, u8)> {
+        self.temperatures.iter().cloned().zip(self.duties.iter().cloned()).collect()
+    }
+}
+
+fn rgb(color: RgbColor) -> [u8; 3] {
+    [color.0, color.1, color.2]
+}
+
+#[derive(Debug)]
+pub struct Device<B> {
+    backend: B,
+}
+
+impl<'a> Device<usbbulk::Device<'a>> {
+    /// Opens the cooler picked out by `selector`, failing if the selected
+    /// device isn't an Asetek-based cooler.
+    pub fn open_selected(context: &'a libusb::Context, selector: &Selector) -> Result<Device<usbbulk::Device<'a>>> {
+        let (dev, info) = usbbulk::Device::open_selected(context, selector)?;
+        if !info.model.is_asetek() {
+            return Err(Error::invalid_argument("device", format!("selected device is a {}, not an Asetek-based cooler", info.model.name())));
+        }
+        Ok(Self::new(dev))
+    }
+
+    /// Opens the first Asetek-based cooler found.
+    pub fn open(context: &'a libusb::Context) -> Result<Device<usbbulk::Device<'a>>> {
+        Self::open_selected(context, &Selector::Models(&ASETEK_MODELS))
+    }
+}
+
+impl<B: Backend> Device<B> {
+    pub fn new(backend: B) -> Device<B> {
+        Device { backend }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Reads the status without changing any settings.
+    pub fn get_status(&mut self) -> Result<Status> {
+        asetek::exchange(&mut self.backend, &Request::ReadStatus)
+    }
+
+    /// Sets the LED's color, and the color it switches to once the liquid
+    /// reaches `alert_temperature` degrees Celsius.
+    pub fn set_color(&mut self, color: RgbColor, alert_color: RgbColor, alert_temperature: u8) -> Result<Status> {
+        if alert_temperature > MAX_TEMPERATURE {
+            return Err(Error::invalid_argument("alert temperature", format!("{}°C is more than {}°C", alert_temperature, MAX_TEMPERATURE)));
+        }
+        asetek::exchange(&mut self.backend, &Request::Color { color: rgb(color), alert_color: rgb(alert_color), alert_temperature })
+    }
+
+    pub fn set_fan_curve(&mut self, curve: &FanCurve) -> Result<Status> {
+        asetek::exchange(&mut self.backend, &Request::FanCurve { temperatures: curve.temperatures, duties: curve.duties })
+    }
+
+    /// Runs the fan at a fixed duty cycle, as a percentage.
+    pub fn set_fan_duty(&mut self, percent: u8) -> Result<Status> {
+        if percent > 100 {
+            return Err(Error::invalid_argument("percent", format!("{}% is more than 100%", percent)));
+        }
+        asetek::exchange(&mut self.backend, &Request::FixedFan(percent))
+    }
+
+    pub fn set_pump_mode(&mut self, mode: PumpMode) -> Result<Status> {
+        asetek::exchange(&mut self.backend, &Request::Pump(mode))
+    }
+}
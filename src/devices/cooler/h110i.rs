@@ -1,26 +1,130 @@
+//! # Corsair Link HID coolers
+//!
+//! The first generation of Corsair Link coolers (the H80i, H100i and H110i)
+//! and the Commander Mini all share one USB product ID and the register
+//! protocol in `protocol::usbhid`. They are told apart by their `DeviceId`
+//! register, and differ in how many LEDs, fans and temperature sensors they
+//! have and whether one of the fan channels drives a pump. This driver was
+//! written against the H110i, and covers the rest of the family through the
+//! capability table in `CoolerModel`.
+
+use std::convert::TryFrom;
 use std::fmt;
+use std::iter;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
 use errors::*;
 
 pub use backends::usbhid as backend;
+use backends::Backend;
 use libusb;
+use devices::Model;
 use protocol::usbhid;
 use protocol::usbhid::Command;
-use protocol::usbhid::TxPacket;
+use protocol::usbhid::RxPacket;
+use sensors::TemperatureSource;
 
 use byteorder::{ByteOrder, LittleEndian};
 
-pub const VENDOR_ID: u16 = 0x1b1c;
+pub const VENDOR_ID: u16 = ::devices::CORSAIR_VENDOR_ID;
 pub const PRODUCT_ID: u16 = 0x0c04;
 
-#[derive(Debug, Copy, Clone)]
+/// A member of the Corsair Link HID family, identified by its `DeviceId`
+/// register.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CoolerModel {
+    H80i,
+    H100i,
+    CommanderMini,
+    H110i,
+}
+
+/// What a model has, according to Corsair's documentation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub led_count: u8,
+    pub temp_sensor_count: u8,
+    /// The number of fan channels, including the pump's.
+    pub fan_count: u8,
+    /// Whether the last fan channel drives a pump.
+    pub pump: bool,
+}
+
+pub const COOLER_MODELS: [CoolerModel; 4] = [
+    CoolerModel::H80i,
+    CoolerModel::H100i,
+    CoolerModel::CommanderMini,
+    CoolerModel::H110i,
+];
+
+impl CoolerModel {
+    pub fn from_device_id(device_id: u8) -> Option<CoolerModel> {
+        COOLER_MODELS.iter().cloned().find(|model| model.device_id() == device_id)
+    }
+
+    pub fn device_id(&self) -> u8 {
+        match *self {
+            CoolerModel::H80i => 0x3b,
+            CoolerModel::H100i => 0x3c,
+            CoolerModel::CommanderMini => 0x3d,
+            CoolerModel::H110i => 0x42,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CoolerModel::H80i => "H80i",
+            CoolerModel::H100i => "H100i",
+            CoolerModel::CommanderMini => "Commander Mini",
+            CoolerModel::H110i => "H110i",
+        }
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        match *self {
+            CoolerModel::H80i => Capabilities { led_count: 1, temp_sensor_count: 1, fan_count: 3, pump: true },
+            CoolerModel::H100i => Capabilities { led_count: 1, temp_sensor_count: 1, fan_count: 5, pump: true },
+            CoolerModel::CommanderMini => Capabilities { led_count: 1, temp_sensor_count: 4, fan_count: 6, pump: false },
+            CoolerModel::H110i => Capabilities { led_count: 1, temp_sensor_count: 1, fan_count: 3, pump: true },
+        }
+    }
+}
+
+impl fmt::Display for CoolerModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A temperature in the firmware's fixed-point format. It is serialized as a
+/// number of degrees Celsius.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(into = "f64", try_from = "f64")]
 pub struct Temperature(u16);
 
 impl Temperature {
-    fn degrees_c(&self) -> f64 {
+    /// Converts from degrees Celsius to the firmware's fixed-point format,
+    /// which counts in 1/256ths of a degree.
+    pub fn from_degrees_c(degrees: f64) -> Result<Temperature> {
+        let value = (degrees * 256.0).round();
+        if !(value >= 0.0 && value <= f64::from(u16::MAX)) {
+            return Err(Error::invalid_argument("temperature", format!("{}°C is outside the range 0°C to {:.3}°C", degrees, f64::from(u16::MAX) / 256.0)));
+        }
+        Ok(Temperature(value as u16))
+    }
+
+    pub fn degrees_c(&self) -> f64 {
         self.0 as f64 / 256.0
     }
 }
 
+impl From<u16> for Temperature {
+    fn from(t: u16) -> Temperature {
+        Temperature(t)
+    }
+}
+
 impl From<Temperature> for u16 {
     fn from(t: Temperature) -> u16 {
         t.0
@@ -33,6 +137,14 @@ impl From<Temperature> for f64 {
     }
 }
 
+impl TryFrom<f64> for Temperature {
+    type Error = Error;
+
+    fn try_from(degrees: f64) -> Result<Temperature> {
+        Temperature::from_degrees_c(degrees)
+    }
+}
+
 impl fmt::Display for Temperature {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}°C", self.degrees_c())
@@ -40,11 +152,12 @@ impl fmt::Display for Temperature {
 }
 
 #[derive(Debug)]
-pub struct Device<'a> {
-    backend: backend::Device<'a>,
+pub struct Device<B> {
+    backend: B,
     command_id: u8,
 
     device_id: u8,
+    model: Option<CoolerModel>,
     firmware_version: String,
     product_name: String,
 
@@ -60,26 +173,56 @@ pub struct Device<'a> {
     pub fan_modes: Vec<FanMode>,
 }
 
-fn increment_command_id(command_id: u8, i: u8) -> u8 {
-    if command_id as u64 + i as u64 > 255 {
-        usbhid::FIRST_COMMAND_ID
-    } else {
-        command_id + i
+impl<'a> Device<backend::Device<'a>> {
+    pub fn open(context: &'a libusb::Context) -> Result<Device<backend::Device<'a>>> {
+        let dev = backend::Device::open(context, VENDOR_ID, PRODUCT_ID)?;
+        Ok(Self::new(dev))
+    }
+
+    /// Opens the cooler picked out by `selector`, failing if the selected
+    /// device isn't a Corsair Link HID device.
+    pub fn open_selected(context: &'a libusb::Context, selector: &backend::Selector) -> Result<Device<backend::Device<'a>>> {
+        let (dev, info) = backend::Device::open_selected(context, selector)?;
+        if info.model != Model::LinkHid {
+            return Err(Error::invalid_argument("device", format!("selected device is a {}, not a {} device", info.model.name(), Model::LinkHid.name())));
+        }
+        Ok(Self::new(dev))
     }
 }
 
-impl<'a> Device<'a> {
-    pub fn open(context: &'a libusb::Context) -> Result<Device<'a>> {
-        let dev = backend::Device::open(context, VENDOR_ID, PRODUCT_ID)?;
+#[cfg(feature = "hidapi")]
+impl Device<::backends::hidraw::Device> {
+    /// Opens the first cooler found through hidapi, leaving the kernel's HID
+    /// driver attached.
+    pub fn open_hidraw(api: &::hidapi::HidApi) -> Result<Device<::backends::hidraw::Device>> {
+        let dev = ::backends::hidraw::Device::open(api, VENDOR_ID, PRODUCT_ID)?;
         Ok(Self::new(dev))
     }
+}
+
+/// Reconciles the number of channels a device reports with the number its
+/// model should have, never going above the latter so that a confused
+/// firmware can't have the driver address channels that don't exist.
+fn channel_count(model: CoolerModel, name: &str, reported: u8, expected: u8) -> u8 {
+    if reported != expected {
+        warn!("{} reports {} {}, but should have {}", model, reported, name, expected);
+    }
+    reported.min(expected)
+}
 
-    pub fn new(backend: backend::Device) -> Device {
+/// The error for a register that read back another register's value.
+fn unexpected_value(register: Register, value: &RegisterValue) -> Error {
+    DecodeError::UnexpectedValue { register: register.into(), value: format!("{:?}", value) }.into()
+}
+
+impl<B: Backend> Device<B> {
+    pub fn new(backend: B) -> Device<B> {
         Device {
             backend,
             command_id: usbhid::FIRST_COMMAND_ID,
 
             device_id: 0,
+            model: None,
             firmware_version: "".to_string(),
             product_name: "".to_string(),
 
@@ -96,16 +239,100 @@ impl<'a> Device<'a> {
         }
     }
 
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn device_id(&self) -> u8 {
+        self.device_id
+    }
+
+    /// Returns the model identified by `get_metadata`, or `None` if the
+    /// device ID is not one of `COOLER_MODELS`.
+    pub fn model(&self) -> Option<CoolerModel> {
+        self.model
+    }
+
+    pub fn firmware_version(&self) -> &str {
+        &self.firmware_version
+    }
+
+    pub fn product_name(&self) -> &str {
+        &self.product_name
+    }
+
+    pub fn led_count(&self) -> u8 {
+        self.led_count
+    }
+
+    pub fn temp_sensor_count(&self) -> u8 {
+        self.temp_sensor_count
+    }
+
+    pub fn fan_count(&self) -> u8 {
+        self.fan_count
+    }
+
+    /// Returns the fan channel that drives the pump, if the model has one.
+    pub fn pump_channel(&self) -> Option<u8> {
+        match self.model {
+            Some(model) if model.capabilities().pump && self.fan_count > 0 => Some(self.fan_count - 1),
+            _ => None,
+        }
+    }
+
+    /// Collects the metadata and polled state into a `Status`. Only what was
+    /// read by the last `get_metadata`, `poll_temperatures`, `poll_fans` and
+    /// `poll_leds` calls is included.
+    pub fn status(&self) -> Status {
+        Status {
+            device_id: self.device_id,
+            model: self.model,
+            firmware_version: self.firmware_version.clone(),
+            product_name: self.product_name.clone(),
+            temperatures: self.temperatures.clone(),
+            fans: self.fan_speeds.iter().zip(self.fan_modes.iter())
+                .map(|(&rpm, &mode)| FanStatus { rpm, mode })
+                .collect(),
+            leds: self.led_modes.iter().zip(self.led_colors.iter()).zip(self.led_cycle_colors.iter())
+                .map(|((&mode, &color), &cycle_colors)| LedStatus { mode, color, cycle_colors })
+                .collect(),
+        }
+    }
+
+    /// Executes a batch of commands, splitting them over as many packets as
+    /// needed, and returns the values read in the order they were requested.
     fn execute(&mut self, commands: Vec<Command<Register, RegisterValue>>) -> Result<Vec<RegisterValue>> {
-        let command_count = commands.len();
-        let tx = TxPacket::new(self.command_id, commands);
-        let rx = self.backend.write_packet(tx)?;
+        let packets = usbhid::packetize(&mut self.command_id, commands)?;
 
-        self.command_id = increment_command_id(self.command_id, command_count as u8);
+        let mut rx = RxPacket::new();
+        for tx in packets {
+            rx.append(self.backend.write_packet(tx)?);
+        }
 
         Ok(rx.read_values())
     }
 
+    /// Reads a single register. LED, sensor and fan registers refer to
+    /// whichever one was last selected.
+    pub fn read_register(&mut self, register: Register) -> Result<RegisterValue> {
+        match self.execute(vec![Command::Read(register)])?.pop() {
+            Some(value) => Ok(value),
+            None => Err(DecodeError::MissingValue { register: register.into() }.into()),
+        }
+    }
+
+    /// Writes a single register, without checking that the write took
+    /// effect.
+    pub fn write_register(&mut self, register: Register, value: RegisterValue) -> Result<()> {
+        self.execute(vec![Command::Write(register, value)])?;
+        Ok(())
+    }
+
     pub fn get_metadata(&mut self) -> Result<()> {
         let values = self.execute(vec![
             Command::Read(Register::DeviceId),
@@ -128,6 +355,22 @@ impl<'a> Device<'a> {
             }
         };
 
+        self.model = CoolerModel::from_device_id(self.device_id);
+        let model = match self.model {
+            Some(model) => model,
+            None => {
+                // Keep working with whatever channels an unknown cooler reports
+                warn!("Unknown device ID 0x{:02x}; using the {} LEDs, {} temperature sensors and {} fans it reports",
+                      self.device_id, self.led_count, self.temp_sensor_count, self.fan_count);
+                return Ok(());
+            },
+        };
+
+        let capabilities = model.capabilities();
+        self.led_count = channel_count(model, "LEDs", self.led_count, capabilities.led_count);
+        self.temp_sensor_count = channel_count(model, "temperature sensors", self.temp_sensor_count, capabilities.temp_sensor_count);
+        self.fan_count = channel_count(model, "fans", self.fan_count, capabilities.fan_count);
+
         Ok(())
     }
 
@@ -138,7 +381,11 @@ impl<'a> Device<'a> {
             commands.push(Command::Read(Register::TempSensorValue));
         }
 
-        for value in self.execute(commands)? {
+        let values = self.execute(commands)?;
+
+        self.temperatures.clear();
+
+        for value in values {
             match value {
                 RegisterValue::TempSensorValue(lb, hb) => self.temperatures.push(Temperature(LittleEndian::read_u16(&[lb, hb]))),
                 _ => (),
@@ -151,7 +398,7 @@ impl<'a> Device<'a> {
     pub fn poll_leds(&mut self) -> Result<()> {
         let mut commands: Vec<Command<Register, RegisterValue>> = Vec::new();
         for i in 0..self.led_count {
-            commands.push(Command::Write(Register::LedSelect, RegisterValue::FanSelect(i as u8)));
+            commands.push(Command::Write(Register::LedSelect, RegisterValue::LedSelect(i)));
             commands.push(Command::Read(Register::LedMode));
             commands.push(Command::Read(Register::LedColor));
             commands.push(Command::Read(Register::LedCycleColors));
@@ -175,10 +422,15 @@ impl<'a> Device<'a> {
         Ok(())
     }
 
-    pub fn set_led_colors(&mut self, led: u8, colors: [RgbColor; 4]) -> Result<()> {
-        if led > self.led_count {
-            return Err("Invalid led specified".into());
+    fn check_led(&self, led: u8) -> Result<()> {
+        if led >= self.led_count {
+            return Err(Error::invalid_argument("led", format!("LED {} does not exist; the device has {}", led, self.led_count)));
         }
+        Ok(())
+    }
+
+    pub fn set_led_colors(&mut self, led: u8, colors: [RgbColor; 4]) -> Result<()> {
+        self.check_led(led)?;
 
         self.execute(vec![
             Command::Write(Register::LedSelect, RegisterValue::LedSelect(led)),
@@ -188,7 +440,42 @@ impl<'a> Device<'a> {
         Ok(())
     }
 
+    /// Reads the colors an LED shows in temperature mode.
+    pub fn get_led_gradient(&mut self, led: u8) -> Result<TemperatureGradient> {
+        self.check_led(led)?;
+
+        let values = self.execute(vec![
+            Command::Write(Register::LedSelect, RegisterValue::LedSelect(led)),
+            Command::Read(Register::LedTemperatureModeTemps),
+            Command::Read(Register::LedTemperatureModeColors),
+        ])?;
+
+        let mut gradient = TemperatureGradient { temperatures: [Temperature(0); 3], colors: [RgbColor(0, 0, 0); 3] };
+        for value in values {
+            match value {
+                RegisterValue::LedTemperatureModeTemps(temperatures) => gradient.temperatures = temperatures,
+                RegisterValue::LedTemperatureModeColors(colors) => gradient.colors = colors,
+                _ => (),
+            }
+        }
+        Ok(gradient)
+    }
+
+    /// Stores the colors an LED shows in temperature mode. The LED only uses
+    /// them once it is switched to `LedMode::Temperature`.
+    pub fn set_led_gradient(&mut self, led: u8, gradient: &TemperatureGradient) -> Result<()> {
+        self.check_led(led)?;
+
+        self.write_verified((Register::LedSelect, RegisterValue::LedSelect(led)), vec![
+            (Register::LedTemperatureModeTemps, RegisterValue::LedTemperatureModeTemps(gradient.temperatures)),
+            (Register::LedTemperatureModeColors, RegisterValue::LedTemperatureModeColors(gradient.colors)),
+        ])
+    }
+
     pub fn set_led_mode(&mut self, mode: LedMode) -> Result<()> {
+        if let LedMode::Temperature(channel) = mode {
+            self.check_temp_channel(channel)?;
+        }
         self.execute(vec![
             Command::Write(Register::LedSelect, RegisterValue::LedSelect(0)),
             Command::Write(Register::LedMode, RegisterValue::LedMode(mode)),
@@ -197,6 +484,211 @@ impl<'a> Device<'a> {
         Ok(())
     }
 
+    fn check_fan(&self, fan: u8) -> Result<()> {
+        if fan >= self.fan_count {
+            return Err(Error::invalid_argument("fan", format!("fan {} does not exist; the device has {}", fan, self.fan_count)));
+        }
+        Ok(())
+    }
+
+    /// Checks that a channel names one of the device's sensors.
+    fn check_temp_channel(&self, channel: TempChannel) -> Result<()> {
+        match channel {
+            TempChannel::Sensor(0) =>
+                Err(Error::invalid_argument("temperature channel", "sensor 0 is `InternalSensor`")),
+            TempChannel::Sensor(sensor) if sensor >= self.temp_sensor_count =>
+                Err(Error::invalid_argument("temperature channel", format!("sensor {} does not exist; the device has {}", sensor, self.temp_sensor_count))),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_fan_register(&mut self, fan: u8, register: Register) -> Result<RegisterValue> {
+        let values = self.execute(vec![
+            Command::Write(Register::FanSelect, RegisterValue::FanSelect(fan)),
+            Command::Read(register),
+        ])?;
+
+        match values.into_iter().next() {
+            Some(value) => Ok(value),
+            None => Err(DecodeError::MissingValue { register: register.into() }.into()),
+        }
+    }
+
+    fn read_fan_mode(&mut self, fan: u8) -> Result<FanMode> {
+        match self.read_fan_register(fan, Register::FanMode)? {
+            RegisterValue::FanMode(mode) => Ok(mode),
+            value => Err(unexpected_value(Register::FanMode, &value)),
+        }
+    }
+
+    /// Writes a set of registers after selecting an LED, sensor or fan, then
+    /// reads them back to check that the firmware accepted every value.
+    fn write_verified(&mut self, select: (Register, RegisterValue), writes: Vec<(Register, RegisterValue)>) -> Result<()> {
+        let mut commands = vec![Command::Write(select.0, select.1.clone())];
+        commands.extend(writes.iter().map(|&(register, ref value)| Command::Write(register, value.clone())));
+        self.execute(commands)?;
+
+        let mut commands = vec![Command::Write(select.0, select.1)];
+        commands.extend(writes.iter().map(|&(register, _)| Command::Read(register)));
+        let values = self.execute(commands)?;
+
+        for (&(register, ref written), read) in writes.iter().zip(values.iter()) {
+            let applied = match (written, read) {
+                // The firmware fills in the fan detected bit itself
+                (&RegisterValue::FanMode(written), &RegisterValue::FanMode(read)) =>
+                    written.profile == read.profile && written.temp_channel == read.temp_channel,
+                _ => written == read,
+            };
+            if !applied {
+                return Err(ProtocolError::WriteNotApplied {
+                    register: register.into(),
+                    written: format!("{:?}", written),
+                    read: format!("{:?}", read),
+                }.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_fan_registers(&mut self, fan: u8, writes: Vec<(Register, RegisterValue)>) -> Result<()> {
+        self.write_verified((Register::FanSelect, RegisterValue::FanSelect(fan)), writes)
+    }
+
+    /// Switches a fan to one of the firmware's profiles, keeping its current
+    /// temperature channel.
+    pub fn set_fan_mode(&mut self, fan: u8, profile: FanProfile) -> Result<()> {
+        self.check_fan(fan)?;
+        let mode = self.read_fan_mode(fan)?.with_profile(profile);
+        self.write_fan_registers(fan, vec![(Register::FanMode, RegisterValue::FanMode(mode))])
+    }
+
+    /// Runs a fan at a fixed duty cycle, given as a percentage.
+    pub fn set_fan_pwm(&mut self, fan: u8, percent: u8) -> Result<()> {
+        self.check_fan(fan)?;
+        if percent > 100 {
+            return Err(Error::invalid_argument("percent", format!("{}% is more than 100%", percent)));
+        }
+
+        let pwm = ((percent as u16 * 255 + 50) / 100) as u8;
+        let mode = self.read_fan_mode(fan)?.with_profile(FanProfile::FixedPWM);
+        self.write_fan_registers(fan, vec![
+            (Register::FanFixedPWM, RegisterValue::FanFixedPWM(pwm)),
+            (Register::FanMode, RegisterValue::FanMode(mode)),
+        ])
+    }
+
+    /// Reads the duty cycle, as a percentage, that a fan runs at in the
+    /// `FixedPWM` profile.
+    pub fn get_fan_pwm(&mut self, fan: u8) -> Result<u8> {
+        self.check_fan(fan)?;
+        match self.read_fan_register(fan, Register::FanFixedPWM)? {
+            RegisterValue::FanFixedPWM(pwm) => Ok(((pwm as u16 * 100 + 127) / 255) as u8),
+            value => Err(unexpected_value(Register::FanFixedPWM, &value)),
+        }
+    }
+
+    /// Reads the speed a fan runs at in the `FixedRPM` profile.
+    pub fn get_fan_rpm(&mut self, fan: u8) -> Result<u16> {
+        self.check_fan(fan)?;
+        match self.read_fan_register(fan, Register::FanFixedRPM)? {
+            RegisterValue::FanFixedRPM(rpm) => Ok(rpm),
+            value => Err(unexpected_value(Register::FanFixedRPM, &value)),
+        }
+    }
+
+    /// Runs a fan at a fixed speed, which the firmware maintains by adjusting
+    /// its duty cycle.
+    pub fn set_fan_rpm(&mut self, fan: u8, rpm: u16) -> Result<()> {
+        self.check_fan(fan)?;
+
+        let mode = self.read_fan_mode(fan)?.with_profile(FanProfile::FixedRPM);
+        self.write_fan_registers(fan, vec![
+            (Register::FanFixedRPM, RegisterValue::FanFixedRPM(rpm)),
+            (Register::FanMode, RegisterValue::FanMode(mode)),
+        ])
+    }
+
+    /// Chooses the temperature a fan's profile follows. With
+    /// `TempChannel::Manual` it follows the temperature reported by the host
+    /// through `report_temperature`.
+    pub fn set_fan_temp_channel(&mut self, fan: u8, channel: TempChannel) -> Result<()> {
+        self.check_fan(fan)?;
+        self.check_temp_channel(channel)?;
+        let mode = self.read_fan_mode(fan)?.with_temp_channel(channel);
+        self.write_fan_registers(fan, vec![(Register::FanMode, RegisterValue::FanMode(mode))])
+    }
+
+    /// Reports a temperature measured by the host to every fan and LED. Those
+    /// using `TempChannel::Manual` follow it.
+    pub fn report_temperature(&mut self, temperature: Temperature) -> Result<()> {
+        let mut commands: Vec<Command<Register, RegisterValue>> = Vec::new();
+        for i in 0..self.fan_count {
+            commands.push(Command::Write(Register::FanSelect, RegisterValue::FanSelect(i)));
+            commands.push(Command::Write(Register::FanReportExtTemp, RegisterValue::FanReportExtTemp(temperature)));
+        }
+        for i in 0..self.led_count {
+            commands.push(Command::Write(Register::LedSelect, RegisterValue::LedSelect(i)));
+            commands.push(Command::Write(Register::LedTemperatureColor, RegisterValue::LedTemperatureColor(temperature)));
+        }
+
+        self.execute(commands)?;
+
+        Ok(())
+    }
+
+    /// Reads `source` and reports its temperature to the device every
+    /// `interval`, for as long as `keep_running` returns true. Stops at the
+    /// first error from either the source or the device.
+    pub fn follow_temperature<S, F>(&mut self, source: &mut S, interval: Duration, mut keep_running: F) -> Result<()>
+        where S: TemperatureSource + ?Sized, F: FnMut() -> bool
+    {
+        while keep_running() {
+            let temperature = Temperature::from_degrees_c(source.read_degrees_c()?.max(0.0))?;
+            self.report_temperature(temperature)?;
+            debug!("Reported {} to the device", temperature);
+            thread::sleep(interval);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the points of a fan's `Custom` profile. The curve is returned as
+    /// stored, without checking that it is monotonic.
+    pub fn get_fan_curve(&mut self, fan: u8) -> Result<FanCurve> {
+        self.check_fan(fan)?;
+
+        let values = self.execute(vec![
+            Command::Write(Register::FanSelect, RegisterValue::FanSelect(fan)),
+            Command::Read(Register::FanTempTable),
+            Command::Read(Register::FanRPMTable),
+        ])?;
+
+        let mut curve = FanCurve { temperatures: [Temperature(0); 5], rpms: [0; 5] };
+        for value in values {
+            match value {
+                RegisterValue::FanTempTable(temperatures) => curve.temperatures = temperatures,
+                RegisterValue::FanRPMTable(rpms) => curve.rpms = rpms,
+                _ => (),
+            }
+        }
+        Ok(curve)
+    }
+
+    /// Stores a curve in the fan's `Custom` profile and switches the fan to
+    /// it. The curve lives in the firmware, so it keeps working without any
+    /// host software running.
+    pub fn set_fan_curve(&mut self, fan: u8, curve: &FanCurve) -> Result<()> {
+        self.check_fan(fan)?;
+
+        let mode = self.read_fan_mode(fan)?.with_profile(FanProfile::Custom);
+        self.write_fan_registers(fan, vec![
+            (Register::FanTempTable, RegisterValue::FanTempTable(curve.temperatures)),
+            (Register::FanRPMTable, RegisterValue::FanRPMTable(curve.rpms)),
+            (Register::FanMode, RegisterValue::FanMode(mode)),
+        ])
+    }
+
     pub fn poll_fans(&mut self) -> Result<()> {
         let mut commands: Vec<Command<Register, RegisterValue>> = Vec::new();
         for i in 0..self.fan_count {
@@ -205,7 +697,12 @@ impl<'a> Device<'a> {
             commands.push(Command::Read(Register::FanMode));
         }
 
-        for value in self.execute(commands)? {
+        let values = self.execute(commands)?;
+
+        self.fan_speeds.clear();
+        self.fan_modes.clear();
+
+        for value in values {
             match value {
                 RegisterValue::FanRPM(rpm) => self.fan_speeds.push(rpm),
                 RegisterValue::FanMode(mode) => self.fan_modes.push(mode),
@@ -215,6 +712,81 @@ impl<'a> Device<'a> {
 
         Ok(())
     }
+
+    /// Reads `registers` from each of `count` LEDs, sensors or fans.
+    fn read_channels(&mut self, select: fn(u8) -> RegisterValue, count: u8, registers: &[Register]) -> Result<Vec<Vec<RegisterValue>>> {
+        let mut channels = Vec::new();
+        for i in 0..count {
+            let select = select(i);
+            let mut commands = vec![Command::Write(select.register(), select)];
+            commands.extend(registers.iter().map(|&register| Command::Read(register)));
+
+            let values = self.execute(commands)?;
+            if let Some(&register) = registers.get(values.len()) {
+                return Err(DecodeError::MissingValue { register: register.into() }.into());
+            }
+            channels.push(values);
+        }
+        Ok(channels)
+    }
+
+    /// Reads every register of every LED, sensor and fan. The device must
+    /// have had its metadata read.
+    pub fn snapshot(&mut self) -> Result<Snapshot> {
+        let status = match self.read_register(Register::Status)? {
+            RegisterValue::Status(status) => status,
+            value => return Err(unexpected_value(Register::Status, &value)),
+        };
+
+        Ok(Snapshot {
+            version: SNAPSHOT_VERSION,
+            device_id: self.device_id,
+            firmware_version: self.firmware_version.clone(),
+            product_name: self.product_name.clone(),
+            status,
+            leds: self.read_channels(RegisterValue::LedSelect, self.led_count, &SNAPSHOT_LED_REGISTERS)?,
+            temp_sensors: self.read_channels(RegisterValue::TempSensorSelect, self.temp_sensor_count, &SNAPSHOT_TEMP_SENSOR_REGISTERS)?,
+            fans: self.read_channels(RegisterValue::FanSelect, self.fan_count, &SNAPSHOT_FAN_REGISTERS)?,
+        })
+    }
+
+    /// Writes the settings in a snapshot back to the device, checking each
+    /// channel's registers once written. Measurements such as temperatures
+    /// and speeds are skipped. The device must have had its metadata read,
+    /// and must be the same model with the same channels as the one the
+    /// snapshot was taken from.
+    pub fn restore_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(Error::invalid_argument("snapshot", format!("version {} is not supported; expected {}", snapshot.version, SNAPSHOT_VERSION)));
+        }
+        if snapshot.device_id != self.device_id {
+            return Err(Error::invalid_argument("snapshot", format!("taken from device 0x{:02x}, but this is device 0x{:02x}", snapshot.device_id, self.device_id)));
+        }
+
+        let channels = [
+            ("LEDs", &snapshot.leds, self.led_count, RegisterValue::LedSelect as fn(u8) -> RegisterValue),
+            ("temperature sensors", &snapshot.temp_sensors, self.temp_sensor_count, RegisterValue::TempSensorSelect),
+            ("fans", &snapshot.fans, self.fan_count, RegisterValue::FanSelect),
+        ];
+        for &(name, values, count, _) in &channels {
+            if values.len() != count as usize {
+                return Err(Error::invalid_argument("snapshot", format!("has {} {}, but the device has {}", values.len(), name, count)));
+            }
+        }
+
+        for &(_, values, _, select) in &channels {
+            for (i, values) in values.iter().enumerate() {
+                let select = select(i as u8);
+                let writes = values.iter()
+                    .filter(|value| value.register().is_setting())
+                    .map(|value| (value.register(), value.clone()))
+                    .collect();
+                self.write_verified((select.register(), select), writes)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[repr(u8)]
@@ -229,9 +801,9 @@ pub enum Register {
     LedCount = 0x05,
     LedMode = 0x06,
     LedColor = 0x07,
-    //LedTemperatureColor = 0x08,
-    //LedTemperatureModeTemps = 0x09,
-    //LedTemperatureModeColors = 0x0a,
+    LedTemperatureColor = 0x08,
+    LedTemperatureModeTemps = 0x09,
+    LedTemperatureModeColors = 0x0a,
     LedCycleColors = 0x0b,
 
     TempSensorSelect = 0x0c,
@@ -241,15 +813,71 @@ pub enum Register {
 
     FanSelect = 0x10,
     FanCount = 0x11,
-    FanMode = 0x012,
-    //FanFixedPWM = 0x13,
-    //FanFixedRPM = 0x14,
-    //FanReportExtTemp = 0x15,
+    FanMode = 0x12,
+    FanFixedPWM = 0x13,
+    FanFixedRPM = 0x14,
+    FanReportExtTemp = 0x15,
     FanRPM = 0x16,
-    //FanMaxRecordedRPM = 0x17,
-    //FanUnderSpeedThreshold = 0x18,
-    //FanRPMTable = 0x19,
-    //FanTempTable = 0x1a,
+    FanMaxRecordedRPM = 0x17,
+    FanUnderSpeedThreshold = 0x18,
+    FanRPMTable = 0x19,
+    FanTempTable = 0x1a,
+}
+
+impl Register {
+    pub fn from_u8(register: u8) -> Option<Register> {
+        Some(match register {
+            0x00 => Register::DeviceId,
+            0x01 => Register::FirmwareVersion,
+            0x02 => Register::ProductName,
+            0x03 => Register::Status,
+
+            0x04 => Register::LedSelect,
+            0x05 => Register::LedCount,
+            0x06 => Register::LedMode,
+            0x07 => Register::LedColor,
+            0x08 => Register::LedTemperatureColor,
+            0x09 => Register::LedTemperatureModeTemps,
+            0x0a => Register::LedTemperatureModeColors,
+            0x0b => Register::LedCycleColors,
+
+            0x0c => Register::TempSensorSelect,
+            0x0d => Register::TempSensorCount,
+            0x0e => Register::TempSensorValue,
+            0x0f => Register::TempSensorLimit,
+
+            0x10 => Register::FanSelect,
+            0x11 => Register::FanCount,
+            0x12 => Register::FanMode,
+            0x13 => Register::FanFixedPWM,
+            0x14 => Register::FanFixedRPM,
+            0x15 => Register::FanReportExtTemp,
+            0x16 => Register::FanRPM,
+            0x17 => Register::FanMaxRecordedRPM,
+            0x18 => Register::FanUnderSpeedThreshold,
+            0x19 => Register::FanRPMTable,
+            0x1a => Register::FanTempTable,
+
+            _ => return None,
+        })
+    }
+
+    /// Returns true if the register holds a setting, rather than a
+    /// measurement or something fixed by the hardware.
+    fn is_setting(&self) -> bool {
+        matches!(*self,
+                 Register::LedMode |
+                 Register::LedTemperatureModeTemps |
+                 Register::LedTemperatureModeColors |
+                 Register::LedCycleColors |
+                 Register::TempSensorLimit |
+                 Register::FanMode |
+                 Register::FanFixedPWM |
+                 Register::FanFixedRPM |
+                 Register::FanUnderSpeedThreshold |
+                 Register::FanRPMTable |
+                 Register::FanTempTable)
+    }
 }
 
 impl Into<u8> for Register {
@@ -268,9 +896,9 @@ impl usbhid::Register for Register {
             &Register::LedCount => 1,
             &Register::LedMode => 1,
             &Register::LedColor => 3,
-            //&Register::LedTemperatureColor => 2,
-            //&Register::LedTemperatureModeTemps => 6,
-            //&Register::LedTemperatureModeColors => 9,
+            &Register::LedTemperatureColor => 2,
+            &Register::LedTemperatureModeTemps => 6,
+            &Register::LedTemperatureModeColors => 9,
             &Register::LedCycleColors => 12,
 
             &Register::TempSensorSelect => 1,
@@ -281,36 +909,54 @@ impl usbhid::Register for Register {
             &Register::FanSelect => 1,
             &Register::FanCount => 1,
             &Register::FanMode => 1,
-            //&Register::FanFixedPWM => 1,
-            //&Register::FanFixedRPM => 2,
-            //&Register::FanReportExtTemp => 2,
+            &Register::FanFixedPWM => 1,
+            &Register::FanFixedRPM => 2,
+            &Register::FanReportExtTemp => 2,
             &Register::FanRPM => 2,
-            //&Register::FanMaxRecordedRPM => 2,
-            //&Register::FanUnderSpeedThreshold => 2,
-            //&Register::FanRPMTable => 10,
-            //&Register::FanTempTable => 10,
+            &Register::FanMaxRecordedRPM => 2,
+            &Register::FanUnderSpeedThreshold => 2,
+            &Register::FanRPMTable => 10,
+            &Register::FanTempTable => 10,
         }
     }
 }
 
-#[repr(u8)]
-#[derive(Copy, Clone, Debug)]
+/// The temperature followed by a fan profile or an LED in temperature mode.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TempChannel {
-    InternalSensor = 0x0,
-    Manual = 0x7,
+    /// The device's first temperature sensor, channel 0.
+    InternalSensor,
+    /// One of the other sensors on devices with several, such as the
+    /// Commander Mini's probes. Numbered from 1, as channel 0 is
+    /// `InternalSensor`.
+    Sensor(u8),
+    /// The temperature reported by the host.
+    Manual,
 }
 
 impl TempChannel {
-    pub fn decode(data: u8) -> Result<TempChannel> {
+    /// Decodes a channel number, accepting sensors up to the most any model
+    /// has.
+    pub fn decode(data: u8) -> Option<TempChannel> {
+        let max_sensors = COOLER_MODELS.iter().map(|model| model.capabilities().temp_sensor_count).max().unwrap_or(1);
         match data {
-            0x0 => Ok(TempChannel::InternalSensor),
-            0x7 => Ok(TempChannel::Manual),
-            _ => Err("Invalid temperature channel for LED mode".into())
+            0x0 => Some(TempChannel::InternalSensor),
+            0x7 => Some(TempChannel::Manual),
+            sensor if sensor < max_sensors => Some(TempChannel::Sensor(sensor)),
+            _ => None,
+        }
+    }
+
+    fn encode(&self) -> u8 {
+        match *self {
+            TempChannel::InternalSensor => 0x0,
+            TempChannel::Sensor(sensor) => sensor,
+            TempChannel::Manual => 0x7,
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum LedMode {
     Static,
     TwoColorCycle(u8),
@@ -336,12 +982,16 @@ impl LedMode {
     }
 
     fn decode(data: u8) -> Result<LedMode> {
+        let invalid = DecodeError::InvalidByte { register: Register::LedMode as u8, byte: data };
         match data & 0xf0 {
             0x00 => Ok(LedMode::Static),
             0x40 => Ok(LedMode::TwoColorCycle(data & 0x0f)),
             0x80 => Ok(LedMode::FourColorCycle(data & 0x0f)),
-            0xC0 => Ok(LedMode::Temperature(TempChannel::decode(data & 0x0f)?)),
-            _ => Err("Invalid LED mode byte".into())
+            0xC0 => match TempChannel::decode(data & 0x0f) {
+                Some(channel) => Ok(LedMode::Temperature(channel)),
+                None => Err(invalid.into()),
+            },
+            _ => Err(invalid.into()),
         }
     }
 
@@ -350,26 +1000,34 @@ impl LedMode {
             &LedMode::Static => 0x00,
             &LedMode::TwoColorCycle(speed) => 0x40 | (speed & 0x0f),
             &LedMode::FourColorCycle(speed) => 0x80 | (speed & 0x0f),
-            &LedMode::Temperature(channel) => 0xC0 | channel as u8,
+            &LedMode::Temperature(channel) => 0xC0 | channel.encode(),
         }
     }
 
-    fn cycle_speed(&self) -> Result<u8> {
+    pub fn cycle_speed(&self) -> Result<u8> {
         match self {
             &LedMode::TwoColorCycle(speed) | &LedMode::FourColorCycle(speed) => Ok(speed),
-            _ => Err("Cycle speed is not defined for this LED mode".into()),
+            _ => Err(Error::invalid_argument("LED mode", "cycle speed is only defined for color cycle modes")),
         }
     }
 
-    fn temp_channel(&self) -> Result<TempChannel> {
+    pub fn temp_channel(&self) -> Result<TempChannel> {
         match self {
             &LedMode::Temperature(channel) => Ok(channel),
-            _ => Err("Temperature channel is not defined for this LED mode".into()),
+            _ => Err(Error::invalid_argument("LED mode", "temperature channel is only defined for temperature mode")),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+/// The contents of the `FanMode` register:
+///
+/// ```text
+///     bit 7     fan detected (set by the firmware)
+///     bits 6-4  temperature channel
+///     bits 3-1  profile
+///     bit 0     four-pin fan
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FanMode {
     profile: FanProfile,
     fan_detected: bool,
@@ -378,18 +1036,57 @@ pub struct FanMode {
 }
 
 impl FanMode {
+    pub fn profile(&self) -> FanProfile {
+        self.profile
+    }
+
+    pub fn fan_detected(&self) -> bool {
+        self.fan_detected
+    }
+
+    pub fn four_pin(&self) -> bool {
+        self.four_pin
+    }
+
+    pub fn temp_channel(&self) -> TempChannel {
+        self.temp_channel
+    }
+
+    /// Returns a copy of this mode using a different profile.
+    pub fn with_profile(&self, profile: FanProfile) -> FanMode {
+        FanMode { profile, ..*self }
+    }
+
+    /// Returns a copy of this mode following a different temperature channel.
+    pub fn with_temp_channel(&self, temp_channel: TempChannel) -> FanMode {
+        FanMode { temp_channel, ..*self }
+    }
+
     fn decode(data: u8) -> Result<FanMode> {
+        let invalid = DecodeError::InvalidByte { register: Register::FanMode as u8, byte: data };
         Ok(FanMode {
-            profile: FanProfile::decode(data & 0b0000_1110)?,
+            profile: match FanProfile::decode(data & 0b0000_1110) {
+                Some(profile) => profile,
+                None => return Err(invalid.into()),
+            },
             fan_detected: (data & 0b1000_0000) == 0b1000_0000,
             four_pin: (data & 0b0000_0001) == 1,
-            temp_channel: TempChannel::decode(data & 0b0111_0000)?,
+            temp_channel: match TempChannel::decode((data & 0b0111_0000) >> 4) {
+                Some(channel) => channel,
+                None => return Err(invalid.into()),
+            },
         })
     }
+
+    /// Encodes the mode for writing. The fan detected bit is read-only, so
+    /// it is always left clear.
+    fn encode(&self) -> u8 {
+        (self.temp_channel.encode() << 4) | self.profile as u8 | if self.four_pin { 1 } else { 0 }
+    }
 }
 
 #[repr(u8)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum FanProfile {
     FixedPWM = 0x02,
     FixedRPM = 0x04,
@@ -401,8 +1098,8 @@ pub enum FanProfile {
 }
 
 impl FanProfile {
-    fn decode(data: u8) -> Result<FanProfile> {
-        Ok(match data {
+    fn decode(data: u8) -> Option<FanProfile> {
+        Some(match data {
             0x02 => FanProfile::FixedPWM,
             0x04 => FanProfile::FixedRPM,
             0x06 => FanProfile::ProfileDefault,
@@ -410,15 +1107,172 @@ impl FanProfile {
             0x0a => FanProfile::ProfileBalanced,
             0x0c => FanProfile::ProfilePerformance,
             0x0e => FanProfile::Custom,
-            _ => return Err("Invalid Fan Profile".into()),
+            _ => return None,
         })
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// The five points of a fan's `Custom` profile. Between points the firmware
+/// interpolates the target speed from the temperature.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FanCurve {
+    temperatures: [Temperature; 5],
+    rpms: [u16; 5],
+}
+
+impl FanCurve {
+    /// Creates a curve from `(temperature, rpm)` points, which must be given
+    /// in order of strictly increasing temperature, with speeds that never
+    /// decrease.
+    pub fn new(points: [(Temperature, u16); 5]) -> Result<FanCurve> {
+        for pair in points.windows(2) {
+            let ((t0, rpm0), (t1, rpm1)) = (pair[0], pair[1]);
+            if t1.0 <= t0.0 {
+                return Err(Error::invalid_argument("fan curve", format!("temperature {} does not come after {}", t1, t0)));
+            }
+            if rpm1 < rpm0 {
+                return Err(Error::invalid_argument("fan curve", format!("{} RPM at {} is slower than {} RPM at {}", rpm1, t1, rpm0, t0)));
+            }
+        }
+
+        let mut curve = FanCurve { temperatures: [Temperature(0); 5], rpms: [0; 5] };
+        for (i, &(temperature, rpm)) in points.iter().enumerate() {
+            curve.temperatures[i] = temperature;
+            curve.rpms[i] = rpm;
+        }
+        Ok(curve)
+    }
+
+    pub fn points(&self) -> Vec<(Temperature, u16)> {
+        self.temperatures.iter().cloned().zip(self.rpms.iter().cloned()).collect()
+    }
+}
+
+/// The colors of an LED in temperature mode. The firmware blends between the
+/// three colors as the temperature moves between their thresholds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TemperatureGradient {
+    temperatures: [Temperature; 3],
+    colors: [RgbColor; 3],
+}
+
+impl TemperatureGradient {
+    /// Creates a gradient from `(temperature, color)` points, which must be
+    /// given in order of strictly increasing temperature.
+    pub fn new(points: [(Temperature, RgbColor); 3]) -> Result<TemperatureGradient> {
+        for pair in points.windows(2) {
+            let (t0, t1) = (pair[0].0, pair[1].0);
+            if t1.0 <= t0.0 {
+                return Err(Error::invalid_argument("temperature gradient", format!("temperature {} does not come after {}", t1, t0)));
+            }
+        }
+
+        Ok(TemperatureGradient {
+            temperatures: [points[0].0, points[1].0, points[2].0],
+            colors: [points[0].1, points[1].1, points[2].1],
+        })
+    }
+
+    pub fn points(&self) -> Vec<(Temperature, RgbColor)> {
+        self.temperatures.iter().cloned().zip(self.colors.iter().cloned()).collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct RgbColor(pub u8, pub u8, pub u8);
 
-#[derive(Clone, Debug)]
+impl FromStr for RgbColor {
+    type Err = Error;
+
+    /// Parses a color in the `RRGGBB` hex format, optionally preceded by `#`.
+    fn from_str(s: &str) -> Result<RgbColor> {
+        let hex = s.trim_start_matches('#');
+        match u32::from_str_radix(hex, 16) {
+            Ok(rgb) if hex.len() == 6 => Ok(RgbColor((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+            _ => Err(Error::invalid_argument("color", format!("{:?} is not an RRGGBB color", s))),
+        }
+    }
+}
+
+impl fmt::Display for RgbColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+/// The metadata and last polled state of a cooler, for reporting.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub device_id: u8,
+    pub model: Option<CoolerModel>,
+    pub firmware_version: String,
+    pub product_name: String,
+    pub temperatures: Vec<Temperature>,
+    pub fans: Vec<FanStatus>,
+    pub leds: Vec<LedStatus>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FanStatus {
+    pub rpm: u16,
+    pub mode: FanMode,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LedStatus {
+    pub mode: LedMode,
+    pub color: RgbColor,
+    pub cycle_colors: [RgbColor; 4],
+}
+
+/// The version of the `Snapshot` format written by this crate.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Registers saved in a snapshot for each LED, sensor and fan, in the order
+/// they are written back. Modes come last so that a channel only switches
+/// mode once the settings it will use are in place.
+const SNAPSHOT_LED_REGISTERS: [Register; 6] = [
+    Register::LedColor,
+    Register::LedTemperatureColor,
+    Register::LedTemperatureModeTemps,
+    Register::LedTemperatureModeColors,
+    Register::LedCycleColors,
+    Register::LedMode,
+];
+
+const SNAPSHOT_TEMP_SENSOR_REGISTERS: [Register; 2] = [
+    Register::TempSensorValue,
+    Register::TempSensorLimit,
+];
+
+const SNAPSHOT_FAN_REGISTERS: [Register; 9] = [
+    Register::FanRPM,
+    Register::FanMaxRecordedRPM,
+    Register::FanReportExtTemp,
+    Register::FanUnderSpeedThreshold,
+    Register::FanFixedPWM,
+    Register::FanFixedRPM,
+    Register::FanRPMTable,
+    Register::FanTempTable,
+    Register::FanMode,
+];
+
+/// The contents of every register of a device, for saving before
+/// experimenting with its settings and restoring afterwards.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub device_id: u8,
+    pub firmware_version: String,
+    pub product_name: String,
+    pub status: u8,
+    /// The registers of each LED, sensor and fan.
+    pub leds: Vec<Vec<RegisterValue>>,
+    pub temp_sensors: Vec<Vec<RegisterValue>>,
+    pub fans: Vec<Vec<RegisterValue>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum RegisterValue {
     DeviceId(u8),
     FirmwareVersion(String),
@@ -429,6 +1283,13 @@ pub enum RegisterValue {
     LedCount(u8),
     LedMode(LedMode),
     LedColor(RgbColor),
+    /// The temperature shown by an LED in temperature mode when its channel
+    /// is `TempChannel::Manual`.
+    LedTemperatureColor(Temperature),
+    /// The temperatures at which an LED in temperature mode shows each of the
+    /// colors in `LedTemperatureModeColors`.
+    LedTemperatureModeTemps([Temperature; 3]),
+    LedTemperatureModeColors([RgbColor; 3]),
     LedCycleColors([RgbColor; 4]),
 
     TempSensorSelect(u8),
@@ -439,28 +1300,105 @@ pub enum RegisterValue {
     FanSelect(u8),
     FanCount(u8),
     FanMode(FanMode),
+    /// Duty cycle used by the `FixedPWM` profile, from 0 to 255.
+    FanFixedPWM(u8),
+    /// Target speed used by the `FixedRPM` profile.
+    FanFixedRPM(u16),
+    /// A temperature reported by the host, used by fans whose channel is
+    /// `TempChannel::Manual`.
+    FanReportExtTemp(Temperature),
     FanRPM(u16),
+    FanMaxRecordedRPM(u16),
+    /// Speed below which the fan is reported as failing.
+    FanUnderSpeedThreshold(u16),
+    /// Target speeds of the five points of the `Custom` profile curve.
+    FanRPMTable([u16; 5]),
+    /// Temperatures of the five points of the `Custom` profile curve.
+    FanTempTable([Temperature; 5]),
 }
 
 impl RegisterValue {
+    /// Returns the register this value belongs in.
+    pub fn register(&self) -> Register {
+        match *self {
+            RegisterValue::DeviceId(_) => Register::DeviceId,
+            RegisterValue::FirmwareVersion(_) => Register::FirmwareVersion,
+            RegisterValue::ProductName(_) => Register::ProductName,
+            RegisterValue::Status(_) => Register::Status,
+
+            RegisterValue::LedSelect(_) => Register::LedSelect,
+            RegisterValue::LedCount(_) => Register::LedCount,
+            RegisterValue::LedMode(_) => Register::LedMode,
+            RegisterValue::LedColor(_) => Register::LedColor,
+            RegisterValue::LedTemperatureColor(_) => Register::LedTemperatureColor,
+            RegisterValue::LedTemperatureModeTemps(_) => Register::LedTemperatureModeTemps,
+            RegisterValue::LedTemperatureModeColors(_) => Register::LedTemperatureModeColors,
+            RegisterValue::LedCycleColors(_) => Register::LedCycleColors,
+
+            RegisterValue::TempSensorSelect(_) => Register::TempSensorSelect,
+            RegisterValue::TempSensorCount(_) => Register::TempSensorCount,
+            RegisterValue::TempSensorValue(..) => Register::TempSensorValue,
+            RegisterValue::TempSensorLimit(..) => Register::TempSensorLimit,
+
+            RegisterValue::FanSelect(_) => Register::FanSelect,
+            RegisterValue::FanCount(_) => Register::FanCount,
+            RegisterValue::FanMode(_) => Register::FanMode,
+            RegisterValue::FanFixedPWM(_) => Register::FanFixedPWM,
+            RegisterValue::FanFixedRPM(_) => Register::FanFixedRPM,
+            RegisterValue::FanReportExtTemp(_) => Register::FanReportExtTemp,
+            RegisterValue::FanRPM(_) => Register::FanRPM,
+            RegisterValue::FanMaxRecordedRPM(_) => Register::FanMaxRecordedRPM,
+            RegisterValue::FanUnderSpeedThreshold(_) => Register::FanUnderSpeedThreshold,
+            RegisterValue::FanRPMTable(_) => Register::FanRPMTable,
+            RegisterValue::FanTempTable(_) => Register::FanTempTable,
+        }
+    }
+
     fn decode_firmware_version(lb: u8, hb: u8) -> String {
         format!("{:x}.{:x}.{:02x}", (hb & 0xf0) >> 4, hb & 0x0f, lb)
     }
+
+    fn decode_temperature(data: &[u8]) -> Temperature {
+        Temperature(LittleEndian::read_u16(&data[0..2]))
+    }
+
+    fn encode_colors(colors: &[RgbColor], buf: &mut [u8]) -> usize {
+        for (i, color) in colors.iter().enumerate() {
+            buf[i * 3] = color.0;
+            buf[i * 3 + 1] = color.1;
+            buf[i * 3 + 2] = color.2;
+        }
+        colors.len() * 3
+    }
+
+    fn encode_words<I: IntoIterator<Item = u16>>(words: I, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        for word in words {
+            LittleEndian::write_u16(&mut buf[len..len + 2], word);
+            len += 2;
+        }
+        len
+    }
 }
 
 impl usbhid::Value<Register> for RegisterValue {
-    type DecodeError = &'static str;
-
     fn decode(register: Register, data: &[u8]) -> Result<Self> {
+        let size = usbhid::Register::size(&register);
+        if data.len() < size {
+            return Err(DecodeError::TruncatedValue { register: register.into(), expected: size, actual: data.len() }.into());
+        }
+
         match register {
             Register::DeviceId => Ok(RegisterValue::DeviceId(data[0])),
             Register::FirmwareVersion => Ok(RegisterValue::FirmwareVersion(
                 RegisterValue::decode_firmware_version(data[0], data[1]))),
             Register::ProductName => {
                 match data[1..].iter().position(|x| { *x == 0 }) {
-                    Some(n) => Ok(RegisterValue::ProductName(
-                        String::from_utf8(data[1..n+1].to_vec())?)),
-                    None => return Err("No null byte found while parsing product name string".into()),
+                    Some(n) => match String::from_utf8(data[1..n+1].to_vec()) {
+                        Ok(s) => Ok(RegisterValue::ProductName(s)),
+                        Err(error) => Err(DecodeError::InvalidString { register: register.into(), error }.into()),
+                    },
+                    None => Err(DecodeError::UnterminatedString { register: register.into() }.into()),
                 }
             },
             Register::Status => Ok(RegisterValue::Status(data[0])),
@@ -469,6 +1407,18 @@ impl usbhid::Value<Register> for RegisterValue {
             Register::LedCount => Ok(RegisterValue::LedCount(data[0])),
             Register::LedMode => Ok(RegisterValue::LedMode(LedMode::decode(data[0])?)),
             Register::LedColor => Ok(RegisterValue::LedColor(RgbColor(data[0], data[1], data[2]))),
+            Register::LedTemperatureColor => Ok(RegisterValue::LedTemperatureColor(
+                RegisterValue::decode_temperature(data))),
+            Register::LedTemperatureModeTemps => Ok(RegisterValue::LedTemperatureModeTemps([
+                RegisterValue::decode_temperature(&data[0..2]),
+                RegisterValue::decode_temperature(&data[2..4]),
+                RegisterValue::decode_temperature(&data[4..6]),
+            ])),
+            Register::LedTemperatureModeColors => Ok(RegisterValue::LedTemperatureModeColors([
+                RgbColor(data[0], data[1], data[2]),
+                RgbColor(data[3], data[4], data[5]),
+                RgbColor(data[6], data[7], data[8]),
+            ])),
             Register::LedCycleColors => Ok(RegisterValue::LedCycleColors([
                 RgbColor(data[0], data[1], data[2]),
                 RgbColor(data[3], data[4], data[5]),
@@ -485,8 +1435,26 @@ impl usbhid::Value<Register> for RegisterValue {
             Register::FanCount => Ok(RegisterValue::FanCount(data[0])),
             Register::FanRPM => Ok(RegisterValue::FanRPM(LittleEndian::read_u16(&data[0..2]))),
             Register::FanMode => Ok(RegisterValue::FanMode(FanMode::decode(data[0])?)),
-
-            //_ => Err("Unhandled register".into()),
+            Register::FanFixedPWM => Ok(RegisterValue::FanFixedPWM(data[0])),
+            Register::FanFixedRPM => Ok(RegisterValue::FanFixedRPM(LittleEndian::read_u16(&data[0..2]))),
+            Register::FanReportExtTemp => Ok(RegisterValue::FanReportExtTemp(
+                RegisterValue::decode_temperature(data))),
+            Register::FanMaxRecordedRPM => Ok(RegisterValue::FanMaxRecordedRPM(LittleEndian::read_u16(&data[0..2]))),
+            Register::FanUnderSpeedThreshold => Ok(RegisterValue::FanUnderSpeedThreshold(LittleEndian::read_u16(&data[0..2]))),
+            Register::FanRPMTable => {
+                let mut rpms = [0u16; 5];
+                for (i, rpm) in rpms.iter_mut().enumerate() {
+                    *rpm = LittleEndian::read_u16(&data[i * 2..i * 2 + 2]);
+                }
+                Ok(RegisterValue::FanRPMTable(rpms))
+            },
+            Register::FanTempTable => {
+                let mut temps = [Temperature(0); 5];
+                for (i, temp) in temps.iter_mut().enumerate() {
+                    *temp = RegisterValue::decode_temperature(&data[i * 2..i * 2 + 2]);
+                }
+                Ok(RegisterValue::FanTempTable(temps))
+            },
         }
     }
 
@@ -494,6 +1462,9 @@ impl usbhid::Value<Register> for RegisterValue {
         match self {
             &RegisterValue::LedSelect(led) => { buf[0] = led; Some(1) },
             &RegisterValue::LedMode(mode) => { buf[0] = mode.encode(); Some(1) }
+            &RegisterValue::LedTemperatureColor(temp) => Some(RegisterValue::encode_words(iter::once(temp.0), buf)),
+            &RegisterValue::LedTemperatureModeTemps(temps) => Some(RegisterValue::encode_words(temps.iter().map(|t| t.0), buf)),
+            &RegisterValue::LedTemperatureModeColors(colors) => Some(RegisterValue::encode_colors(&colors, buf)),
             &RegisterValue::LedCycleColors(colors) => {
                 buf[0] = colors[0].0; buf[1] = colors[0].1; buf[2] = colors[0].2;
                 buf[3] = colors[1].0; buf[4] = colors[1].1; buf[5] = colors[1].2;
@@ -504,8 +1475,144 @@ impl usbhid::Value<Register> for RegisterValue {
             &RegisterValue::TempSensorSelect(sensor) => { buf[0] = sensor; Some(1) },
             &RegisterValue::TempSensorLimit(lb,hb) => { buf[0] = lb; buf[1] = hb; Some(2) },
             &RegisterValue::FanSelect(fan) => { buf[0] = fan; Some(1) },
+            &RegisterValue::FanMode(mode) => { buf[0] = mode.encode(); Some(1) },
+            &RegisterValue::FanFixedPWM(pwm) => { buf[0] = pwm; Some(1) },
+            &RegisterValue::FanFixedRPM(rpm) => Some(RegisterValue::encode_words(iter::once(rpm), buf)),
+            &RegisterValue::FanReportExtTemp(temp) => Some(RegisterValue::encode_words(iter::once(temp.0), buf)),
+            &RegisterValue::FanUnderSpeedThreshold(rpm) => Some(RegisterValue::encode_words(iter::once(rpm), buf)),
+            &RegisterValue::FanRPMTable(rpms) => Some(RegisterValue::encode_words(rpms.iter().cloned(), buf)),
+            &RegisterValue::FanTempTable(temps) => Some(RegisterValue::encode_words(temps.iter().map(|t| t.0), buf)),
 
             _ => None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backends::simulator::Simulator;
+
+    fn degrees(degrees: f64) -> Temperature {
+        Temperature::from_degrees_c(degrees).unwrap()
+    }
+
+    fn is_invalid_argument<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::InvalidArgument { .. }))
+    }
+
+    #[test]
+    fn temperature_range() {
+        assert_eq!(u16::from(degrees(0.0)), 0);
+        assert_eq!(u16::from(degrees(30.5)), 0x1e80);
+        assert_eq!(u16::from(degrees(255.996)), u16::MAX);
+        assert!(is_invalid_argument(Temperature::from_degrees_c(-0.01)));
+        assert!(is_invalid_argument(Temperature::from_degrees_c(256.0)));
+        assert!(is_invalid_argument(Temperature::from_degrees_c(f64::NAN)));
+    }
+
+    fn curve_points() -> [(Temperature, u16); 5] {
+        [(degrees(25.0), 600), (degrees(30.0), 800), (degrees(35.0), 1200), (degrees(40.0), 1600), (degrees(45.0), 2000)]
+    }
+
+    #[test]
+    fn fan_curve() {
+        let curve = FanCurve::new(curve_points()).unwrap();
+        assert_eq!(curve.points(), curve_points().to_vec());
+
+        let mut flat = curve_points();
+        flat[1].1 = 600;
+        assert!(FanCurve::new(flat).is_ok());
+    }
+
+    #[test]
+    fn fan_curve_temperatures_must_increase() {
+        let mut points = curve_points();
+        points[3].0 = degrees(35.0);
+        assert!(is_invalid_argument(FanCurve::new(points)));
+
+        let mut points = curve_points();
+        points[4].0 = degrees(20.0);
+        assert!(is_invalid_argument(FanCurve::new(points)));
+    }
+
+    #[test]
+    fn fan_curve_speeds_must_not_decrease() {
+        let mut points = curve_points();
+        points[2].1 = 700;
+        assert!(is_invalid_argument(FanCurve::new(points)));
+    }
+
+    fn gradient_points() -> [(Temperature, RgbColor); 3] {
+        [(degrees(30.0), RgbColor(0, 0xff, 0)), (degrees(40.0), RgbColor(0xff, 0xff, 0)), (degrees(50.0), RgbColor(0xff, 0, 0))]
+    }
+
+    #[test]
+    fn temperature_gradient() {
+        let gradient = TemperatureGradient::new(gradient_points()).unwrap();
+        assert_eq!(gradient.points(), gradient_points().to_vec());
+    }
+
+    #[test]
+    fn temperature_gradient_temperatures_must_increase() {
+        let mut points = gradient_points();
+        points[1].0 = degrees(30.0);
+        assert!(is_invalid_argument(TemperatureGradient::new(points)));
+
+        let mut points = gradient_points();
+        points[2].0 = degrees(35.0);
+        assert!(is_invalid_argument(TemperatureGradient::new(points)));
+    }
+
+    fn cooler(model: CoolerModel) -> Device<Simulator> {
+        let mut cooler = Device::new(Simulator::for_model(model));
+        cooler.get_metadata().unwrap();
+        cooler
+    }
+
+    #[test]
+    fn get_metadata_of_unknown_model() {
+        let mut simulator = Simulator::for_model(CoolerModel::H110i);
+        simulator.device_id = 0x99;
+        let mut cooler = Device::new(simulator);
+        cooler.get_metadata().unwrap();
+
+        assert_eq!(cooler.model(), None);
+        assert_eq!((cooler.led_count(), cooler.temp_sensor_count(), cooler.fan_count()), (1, 1, 3));
+        assert_eq!(cooler.pump_channel(), None);
+        cooler.poll_fans().unwrap();
+        assert_eq!(cooler.status().fans.len(), 3);
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut cooler = cooler(CoolerModel::H110i);
+        let snapshot = cooler.snapshot().unwrap();
+
+        cooler.set_led_colors(0, [RgbColor(0xff, 0, 0); 4]).unwrap();
+        cooler.set_led_mode(LedMode::FourColorCycle(2)).unwrap();
+        cooler.set_fan_pwm(0, 70).unwrap();
+        cooler.set_fan_curve(1, &FanCurve::new(curve_points()).unwrap()).unwrap();
+        cooler.set_fan_rpm(2, 1500).unwrap();
+        assert_ne!(cooler.snapshot().unwrap(), snapshot);
+
+        cooler.restore_snapshot(&snapshot).unwrap();
+        assert_eq!(cooler.snapshot().unwrap(), snapshot);
+        let simulator = cooler.backend();
+        assert_eq!(simulator.leds[0].mode, 0x00);
+        assert_eq!(simulator.leds[0].cycle_colors, [0; 12]);
+        assert_eq!((simulator.fans[0].mode, simulator.fans[0].fixed_pwm), (0x87, 0x80));
+        assert_eq!(simulator.fans[1].rpm_table, [0xe8, 0x03, 0xb0, 0x04, 0x78, 0x05, 0x40, 0x06, 0x08, 0x07]);
+        assert_eq!((simulator.fans[2].mode, simulator.fans[2].fixed_rpm), (0x87, 1000));
+    }
+
+    #[test]
+    fn snapshot_from_another_device_is_rejected() {
+        let snapshot = cooler(CoolerModel::H110i).snapshot().unwrap();
+
+        let mut other = cooler(CoolerModel::H100i);
+        other.set_fan_pwm(0, 70).unwrap();
+        assert!(is_invalid_argument(other.restore_snapshot(&snapshot)));
+        assert_eq!(other.backend().fans[0].fixed_pwm, 179);
+    }
+}
@@ -1 +1,2 @@
 pub mod h110i;
+pub mod asetek;
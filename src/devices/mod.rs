@@ -1,2 +1,77 @@
 pub mod cooler;
 pub mod psu;
+
+pub const CORSAIR_VENDOR_ID: u16 = 0x1b1c;
+
+/// The Corsair Link devices this crate knows how to talk to, as identified by
+/// their USB product ID.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Model {
+    /// The H80i, H100i, H110i and Commander Mini, which share a product ID
+    /// and are told apart once opened. See `cooler::h110i::CoolerModel`.
+    LinkHid,
+    HX750i,
+    /// Asetek-based coolers, driven by `cooler::asetek`.
+    H80iGT,
+    H100iGTX,
+    H110iGT,
+    H80iV2,
+    H100iV2,
+    H115i,
+}
+
+/// The Asetek-based coolers, which all share one driver.
+pub const ASETEK_MODELS: [Model; 6] = [
+    Model::H80iGT,
+    Model::H100iGTX,
+    Model::H110iGT,
+    Model::H80iV2,
+    Model::H100iV2,
+    Model::H115i,
+];
+
+impl Model {
+    pub fn from_product_id(product_id: u16) -> Option<Model> {
+        match product_id {
+            0x0c04 => Some(Model::LinkHid),
+            0x1c05 => Some(Model::HX750i),
+            0x0c02 => Some(Model::H80iGT),
+            0x0c03 => Some(Model::H100iGTX),
+            0x0c07 => Some(Model::H110iGT),
+            0x0c08 => Some(Model::H80iV2),
+            0x0c09 => Some(Model::H100iV2),
+            0x0c0a => Some(Model::H115i),
+            _ => None,
+        }
+    }
+
+    pub fn product_id(&self) -> u16 {
+        match *self {
+            Model::LinkHid => cooler::h110i::PRODUCT_ID,
+            Model::HX750i => psu::hx750i::PRODUCT_ID,
+            Model::H80iGT => 0x0c02,
+            Model::H100iGTX => 0x0c03,
+            Model::H110iGT => 0x0c07,
+            Model::H80iV2 => 0x0c08,
+            Model::H100iV2 => 0x0c09,
+            Model::H115i => 0x0c0a,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Model::LinkHid => "Corsair Link HID",
+            Model::HX750i => "HX750i",
+            Model::H80iGT => "H80i GT",
+            Model::H100iGTX => "H100i GTX",
+            Model::H110iGT => "H110i GT",
+            Model::H80iV2 => "H80i v2",
+            Model::H100iV2 => "H100i v2",
+            Model::H115i => "H115i",
+        }
+    }
+
+    pub fn is_asetek(&self) -> bool {
+        ASETEK_MODELS.contains(self)
+    }
+}
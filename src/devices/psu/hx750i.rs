@@ -0,0 +1,452 @@
+//! # Corsair HX750i power supply
+//!
+//! The HX750i is monitored through the USB dongle on its Corsair Link cable,
+//! which speaks the PMBus commands in `protocol::pmbus`.
+//!
+//! Besides monitoring, the fan can be switched between the firmware's own
+//! curve and a fixed duty cycle, and over-current protection (OCP) can watch
+//! the +12V output as a single rail or each rail against its own limit. A
+//! badly set OCP limit shuts the PSU down, so every setting is range checked
+//! before it is written and read back afterwards.
+
+use std::fmt;
+use std::time::Duration;
+use libusb;
+
+use errors::*;
+use backends::Backend;
+use backends::usbhid as backend;
+use devices::Model;
+use protocol::pmbus::{self, Command, Value};
+
+pub const VENDOR_ID: u16 = ::devices::CORSAIR_VENDOR_ID;
+pub const PRODUCT_ID: u16 = 0x1c05;
+
+/// One of the PSU's monitored output rails, selected by its page number.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Rail {
+    V12 = 0,
+    V5 = 1,
+    V3_3 = 2,
+}
+
+pub const RAILS: [Rail; 3] = [Rail::V12, Rail::V5, Rail::V3_3];
+
+/// The lowest fixed fan duty cycle accepted, as a percentage, so that a fixed
+/// speed can't leave the PSU without cooling under load.
+pub const MIN_FIXED_FAN_DUTY: u8 = 30;
+
+/// The lowest OCP limit accepted for any rail, in amps.
+pub const MIN_OCP_LIMIT: f64 = 5.0;
+
+/// LINEAR11 values may read back with less precision than they were written
+/// with; they must match to within this fraction.
+const READ_BACK_TOLERANCE: f64 = 0.01;
+
+impl Rail {
+    /// Returns the rail's rated current in amps, which is the highest OCP
+    /// limit it accepts.
+    pub fn max_current(&self) -> f64 {
+        match *self {
+            Rail::V12 => 62.5,
+            Rail::V5 => 25.0,
+            Rail::V3_3 => 25.0,
+        }
+    }
+}
+
+impl fmt::Display for Rail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rail::V12 => write!(f, "+12V"),
+            Rail::V5 => write!(f, "+5V"),
+            Rail::V3_3 => write!(f, "+3.3V"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FanMode {
+    /// The firmware drives the fan from its own temperature curve.
+    Hardware,
+    /// The fan runs at a fixed duty cycle, as a percentage.
+    Fixed(u8),
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OcpMode {
+    /// OCP trips on the total current drawn from the +12V output.
+    SingleRail = 0x01,
+    /// OCP trips when any rail exceeds its own limit.
+    MultiRail = 0x02,
+}
+
+impl OcpMode {
+    fn decode(data: u8) -> Option<OcpMode> {
+        match data {
+            0x01 => Some(OcpMode::SingleRail),
+            0x02 => Some(OcpMode::MultiRail),
+            _ => None,
+        }
+    }
+}
+
+const FAN_CONTROL_HARDWARE: u8 = 0x00;
+const FAN_CONTROL_FIXED: u8 = 0x01;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RailStatus {
+    pub rail: Rail,
+    pub voltage: f64,
+    pub current: f64,
+    pub power: f64,
+}
+
+/// Everything the PSU reports, read in one go by `Device::poll`. Voltages are
+/// in volts, currents in amps, powers in watts, temperatures in degrees
+/// Celsius and uptimes in seconds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub model: String,
+    pub serial_number: String,
+    pub input_voltage: f64,
+    pub input_power: f64,
+    pub rails: Vec<RailStatus>,
+    /// The sum of the power drawn from each rail.
+    pub output_power: f64,
+    /// Output power as a fraction of input power. The rails don't account
+    /// for standby power, so this slightly underestimates the real
+    /// efficiency.
+    pub efficiency: f64,
+    pub temperatures: [f64; 2],
+    pub fan_rpm: f64,
+    pub fan_mode: FanMode,
+    pub ocp_mode: OcpMode,
+    pub uptime: u64,
+    pub total_uptime: u64,
+}
+
+#[derive(Debug)]
+pub struct Device<B> {
+    backend: B,
+
+    model: String,
+    serial_number: String,
+}
+
+impl<'a> Device<backend::Device<'a>> {
+    pub fn open(context: &'a libusb::Context) -> Result<Device<backend::Device<'a>>> {
+        let dev = backend::Device::open(context, VENDOR_ID, PRODUCT_ID)?;
+        Ok(Self::new(dev))
+    }
+
+    /// Opens the PSU picked out by `selector`, failing if the selected device
+    /// is some other model.
+    pub fn open_selected(context: &'a libusb::Context, selector: &backend::Selector) -> Result<Device<backend::Device<'a>>> {
+        let (dev, info) = backend::Device::open_selected(context, selector)?;
+        if info.model != Model::HX750i {
+            return Err(Error::invalid_argument("device", format!("selected device is a {}, not an HX750i", info.model.name())));
+        }
+        Ok(Self::new(dev))
+    }
+}
+
+#[cfg(feature = "hidapi")]
+impl Device<::backends::hidraw::Device> {
+    /// Opens the first HX750i found through hidapi, leaving the kernel's HID
+    /// driver attached.
+    pub fn open_hidraw(api: &::hidapi::HidApi) -> Result<Device<::backends::hidraw::Device>> {
+        let dev = ::backends::hidraw::Device::open(api, VENDOR_ID, PRODUCT_ID)?;
+        Ok(Self::new(dev))
+    }
+}
+
+/// The error for a command that read back a value in another format.
+fn unexpected_value(command: Command, value: &Value) -> Error {
+    DecodeError::UnexpectedValue { register: command.into(), value: value.to_string() }.into()
+}
+
+impl<B: Backend> Device<B> {
+    pub fn new(backend: B) -> Device<B> {
+        Device {
+            backend,
+
+            model: "".to_string(),
+            serial_number: "".to_string(),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn serial_number(&self) -> &str {
+        &self.serial_number
+    }
+
+    fn read_linear11(&mut self, command: Command) -> Result<f64> {
+        match pmbus::read(&mut self.backend, command)? {
+            Value::Linear11(value) => Ok(value),
+            value => Err(unexpected_value(command, &value)),
+        }
+    }
+
+    fn read_string(&mut self, command: Command) -> Result<String> {
+        match pmbus::read(&mut self.backend, command)? {
+            Value::String(s) => Ok(s),
+            value => Err(unexpected_value(command, &value)),
+        }
+    }
+
+    fn read_seconds(&mut self, command: Command) -> Result<Duration> {
+        match pmbus::read(&mut self.backend, command)? {
+            Value::Seconds(seconds) => Ok(Duration::from_secs(u64::from(seconds))),
+            value => Err(unexpected_value(command, &value)),
+        }
+    }
+
+    fn read_byte(&mut self, command: Command) -> Result<u8> {
+        match pmbus::read(&mut self.backend, command)? {
+            Value::Byte(byte) => Ok(byte),
+            value => Err(unexpected_value(command, &value)),
+        }
+    }
+
+    fn select_rail(&mut self, rail: Rail) -> Result<()> {
+        pmbus::write(&mut self.backend, Command::Page, &Value::Byte(rail as u8))
+    }
+
+    /// Writes a command, then reads it back to check that the PSU accepted
+    /// the value.
+    fn write_verified(&mut self, command: Command, value: Value) -> Result<()> {
+        pmbus::write(&mut self.backend, command, &value)?;
+        let read = pmbus::read(&mut self.backend, command)?;
+
+        let applied = match (&value, &read) {
+            (&Value::Linear11(written), &Value::Linear11(read)) =>
+                (written - read).abs() <= written.abs() * READ_BACK_TOLERANCE,
+            _ => value == read,
+        };
+        if !applied {
+            return Err(ProtocolError::WriteNotApplied {
+                register: command.into(),
+                written: value.to_string(),
+                read: read.to_string(),
+            }.into());
+        }
+        Ok(())
+    }
+
+    /// Wakes the dongle up and reads the PSU's model and serial number.
+    pub fn get_metadata(&mut self) -> Result<()> {
+        pmbus::exchange(&mut self.backend, &pmbus::INIT)?;
+        self.model = self.read_string(Command::MfrModel)?;
+        self.serial_number = self.read_string(Command::MfrSerial)?;
+        Ok(())
+    }
+
+    pub fn input_voltage(&mut self) -> Result<f64> {
+        self.read_linear11(Command::ReadVin)
+    }
+
+    /// Returns the input power, as estimated by the PSU.
+    pub fn input_power(&mut self) -> Result<f64> {
+        self.read_linear11(Command::ReadInputPower)
+    }
+
+    /// Returns the voltage, current and power of one of the output rails.
+    pub fn rail(&mut self, rail: Rail) -> Result<RailStatus> {
+        self.select_rail(rail)?;
+        Ok(RailStatus {
+            rail,
+            voltage: self.read_linear11(Command::ReadVout)?,
+            current: self.read_linear11(Command::ReadIout)?,
+            power: self.read_linear11(Command::ReadPout)?,
+        })
+    }
+
+    pub fn temperatures(&mut self) -> Result<[f64; 2]> {
+        Ok([self.read_linear11(Command::ReadTemperature1)?, self.read_linear11(Command::ReadTemperature2)?])
+    }
+
+    pub fn fan_rpm(&mut self) -> Result<f64> {
+        self.read_linear11(Command::ReadFanSpeed1)
+    }
+
+    pub fn fan_mode(&mut self) -> Result<FanMode> {
+        match self.read_byte(Command::FanControlMode)? {
+            FAN_CONTROL_HARDWARE => Ok(FanMode::Hardware),
+            FAN_CONTROL_FIXED => Ok(FanMode::Fixed(self.read_byte(Command::FanCommand1)?)),
+            byte => Err(DecodeError::InvalidByte { register: Command::FanControlMode.into(), byte }.into()),
+        }
+    }
+
+    /// Hands the fan to the firmware, or runs it at a fixed duty cycle of at
+    /// least `MIN_FIXED_FAN_DUTY` percent.
+    pub fn set_fan_mode(&mut self, mode: FanMode) -> Result<()> {
+        match mode {
+            FanMode::Hardware => self.write_verified(Command::FanControlMode, Value::Byte(FAN_CONTROL_HARDWARE)),
+            FanMode::Fixed(duty) => {
+                if !(MIN_FIXED_FAN_DUTY..=100).contains(&duty) {
+                    return Err(Error::invalid_argument("duty", format!("{}% is outside {}% to 100%", duty, MIN_FIXED_FAN_DUTY)));
+                }
+                // Set the speed first so the fan never runs at a stale one
+                self.write_verified(Command::FanCommand1, Value::Byte(duty))?;
+                self.write_verified(Command::FanControlMode, Value::Byte(FAN_CONTROL_FIXED))
+            },
+        }
+    }
+
+    pub fn ocp_mode(&mut self) -> Result<OcpMode> {
+        let byte = self.read_byte(Command::OcpMode)?;
+        OcpMode::decode(byte).ok_or_else(|| DecodeError::InvalidByte { register: Command::OcpMode.into(), byte }.into())
+    }
+
+    pub fn set_ocp_mode(&mut self, mode: OcpMode) -> Result<()> {
+        self.write_verified(Command::OcpMode, Value::Byte(mode as u8))
+    }
+
+    /// Returns the current, in amps, at which OCP trips for a rail in
+    /// multi-rail mode.
+    pub fn ocp_limit(&mut self, rail: Rail) -> Result<f64> {
+        self.select_rail(rail)?;
+        self.read_linear11(Command::IoutOcFaultLimit)
+    }
+
+    /// Sets the current, in amps, at which OCP trips for a rail in multi-rail
+    /// mode. The limit must be between `MIN_OCP_LIMIT` and the rail's rated
+    /// current, and above what the rail is drawing right now.
+    pub fn set_ocp_limit(&mut self, rail: Rail, amps: f64) -> Result<()> {
+        if !(MIN_OCP_LIMIT..=rail.max_current()).contains(&amps) {
+            return Err(Error::invalid_argument("OCP limit", format!("{}A is outside {}A to {}A for the {} rail", amps, MIN_OCP_LIMIT, rail.max_current(), rail)));
+        }
+
+        self.select_rail(rail)?;
+        let current = self.read_linear11(Command::ReadIout)?;
+        if amps <= current {
+            return Err(Error::invalid_argument("OCP limit", format!("{}A would trip immediately; the {} rail is drawing {:.1}A", amps, rail, current)));
+        }
+        self.write_verified(Command::IoutOcFaultLimit, Value::Linear11(amps))
+    }
+
+    /// Returns how long the PSU has been on since it was last powered up.
+    pub fn uptime(&mut self) -> Result<Duration> {
+        self.read_seconds(Command::ReadUptime)
+    }
+
+    /// Returns how long the PSU has been on over its whole life.
+    pub fn total_uptime(&mut self) -> Result<Duration> {
+        self.read_seconds(Command::ReadTotalUptime)
+    }
+
+    /// Reads every value the PSU reports. The device must have had its
+    /// metadata read.
+    pub fn poll(&mut self) -> Result<Status> {
+        let input_voltage = self.input_voltage()?;
+        let input_power = self.input_power()?;
+        let rails = RAILS.iter().map(|&rail| self.rail(rail)).collect::<Result<Vec<RailStatus>>>()?;
+        let output_power = rails.iter().map(|rail| rail.power).sum();
+
+        Ok(Status {
+            model: self.model.clone(),
+            serial_number: self.serial_number.clone(),
+            input_voltage,
+            input_power,
+            rails,
+            output_power,
+            efficiency: if input_power > 0.0 { output_power / input_power } else { 0.0 },
+            temperatures: self.temperatures()?,
+            fan_rpm: self.fan_rpm()?,
+            fan_mode: self.fan_mode()?,
+            ocp_mode: self.ocp_mode()?,
+            uptime: self.uptime()?.as_secs(),
+            total_uptime: self.total_uptime()?.as_secs(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backends::psu_simulator::PsuSimulator;
+
+    fn psu() -> Device<PsuSimulator> {
+        let mut psu = Device::new(PsuSimulator::new());
+        psu.get_metadata().unwrap();
+        psu
+    }
+
+    fn is_invalid_argument<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::InvalidArgument { .. }))
+    }
+
+    fn is_write_not_applied<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::Protocol(ProtocolError::WriteNotApplied { .. })))
+    }
+
+    #[test]
+    fn set_fan_mode() {
+        let mut psu = psu();
+        psu.set_fan_mode(FanMode::Fixed(60)).unwrap();
+        assert_eq!(psu.fan_mode().unwrap(), FanMode::Fixed(60));
+
+        psu.set_fan_mode(FanMode::Hardware).unwrap();
+        assert_eq!(psu.fan_mode().unwrap(), FanMode::Hardware);
+    }
+
+    #[test]
+    fn fan_duty_below_minimum_is_rejected() {
+        let mut psu = psu();
+        assert!(is_invalid_argument(psu.set_fan_mode(FanMode::Fixed(MIN_FIXED_FAN_DUTY - 1))));
+        assert!(is_invalid_argument(psu.set_fan_mode(FanMode::Fixed(101))));
+        assert_eq!(psu.fan_mode().unwrap(), FanMode::Hardware);
+        assert_eq!(psu.backend().fan_duty, 0);
+    }
+
+    #[test]
+    fn set_ocp_limit() {
+        let mut psu = psu();
+        psu.set_ocp_limit(Rail::V5, 15.0).unwrap();
+        assert_eq!(psu.ocp_limit(Rail::V5).unwrap(), 15.0);
+        assert_eq!(psu.backend().rails[1].ocp_limit, 15.0);
+    }
+
+    #[test]
+    fn ocp_limit_below_current_draw_is_rejected() {
+        let mut psu = psu();
+        // The +12V rail is drawing 15A
+        assert!(is_invalid_argument(psu.set_ocp_limit(Rail::V12, 12.0)));
+        assert!(is_invalid_argument(psu.set_ocp_limit(Rail::V12, 15.0)));
+        assert_eq!(psu.backend().rails[0].ocp_limit, 40.0);
+    }
+
+    #[test]
+    fn ocp_limit_outside_rating_is_rejected() {
+        let mut psu = psu();
+        assert!(is_invalid_argument(psu.set_ocp_limit(Rail::V3_3, MIN_OCP_LIMIT - 1.0)));
+        assert!(is_invalid_argument(psu.set_ocp_limit(Rail::V12, Rail::V12.max_current() + 1.0)));
+        assert_eq!(psu.backend().rails[0].ocp_limit, 40.0);
+        assert_eq!(psu.backend().rails[2].ocp_limit, 20.0);
+    }
+
+    #[test]
+    fn write_that_does_not_read_back_is_refused() {
+        let mut psu = psu();
+        psu.backend_mut().ignored_writes = vec![Command::IoutOcFaultLimit, Command::FanCommand1];
+
+        assert!(is_write_not_applied(psu.set_ocp_limit(Rail::V12, 30.0)));
+        // The fan must not switch to a fixed duty it didn't accept
+        assert!(is_write_not_applied(psu.set_fan_mode(FanMode::Fixed(60))));
+        assert_eq!(psu.fan_mode().unwrap(), FanMode::Hardware);
+    }
+}
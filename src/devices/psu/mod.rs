@@ -1 +1 @@
-mod hx750i;
+pub mod hx750i;
@@ -0,0 +1,255 @@
+//! # Errors
+//!
+//! Every fallible operation in the crate returns an `Error`, which sorts
+//! failures into a handful of categories so that callers can decide how to
+//! react to them:
+//!
+//! * `Transport` errors come from moving reports to and from the device. A
+//!   timeout may be worth retrying, but a missing device means reconnecting.
+//! * `Protocol` errors mean the device answered, but not with a response to
+//!   the request that was sent.
+//! * `Decode` errors mean a response was malformed, or held a value the crate
+//!   doesn't understand.
+//! * `UnsupportedRegister` and `InvalidArgument` are mistakes on the host side
+//!   that retrying won't fix.
+//! * `Sensor` errors come from reading a host temperature sensor.
+//! * `Config` errors mean a configuration file couldn't be read or parsed.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+use std::string;
+use libusb;
+#[cfg(feature = "hidapi")]
+use hidapi;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Transport(TransportError),
+    Protocol(ProtocolError),
+    Decode(DecodeError),
+    /// The register can't be used for the requested operation, such as
+    /// writing to a read-only register.
+    UnsupportedRegister(u8),
+    InvalidArgument { argument: &'static str, reason: String },
+    Sensor(String),
+    Config(String),
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    NoDevice,
+    Usb(libusb::Error),
+    #[cfg(feature = "hidapi")]
+    Hid(hidapi::HidError),
+    Io(io::Error),
+    /// No response arrived for a request.
+    NoResponse,
+    /// A failure reported by a software backend, such as the simulator or a
+    /// replayed capture.
+    Backend(String),
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    BadCommandId { expected: u8, actual: u8 },
+    BadOpcode { command_id: u8, expected: u8, actual: u8 },
+    /// A command or packet is too large to fit in a single report.
+    PacketTooLarge { len: usize },
+    /// A register read back a different value than was just written to it.
+    WriteNotApplied { register: u8, written: String, read: String },
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    TruncatedPacket { expected: usize, actual: usize },
+    TruncatedValue { register: u8, expected: usize, actual: usize },
+    BadBlockLength { register: u8, expected: u8, actual: u8 },
+    /// A register held a byte that doesn't correspond to any known value, such
+    /// as an unknown LED mode or fan profile.
+    InvalidByte { register: u8, byte: u8 },
+    InvalidString { register: u8, error: string::FromUtf8Error },
+    /// A response held no value for a register that was read.
+    MissingValue { register: u8 },
+    /// A register decoded to a value that belongs to a different register.
+    UnexpectedValue { register: u8, value: String },
+    UnterminatedString { register: u8 },
+    /// A PMBus response didn't echo the flag and command of its request.
+    BadEcho { expected: [u8; 2], actual: [u8; 2] },
+    /// An Asetek request began with a command byte that isn't known.
+    UnknownCommand(u8),
+    InvalidCapture(String),
+}
+
+impl Error {
+    pub fn invalid_argument<S: Into<String>>(argument: &'static str, reason: S) -> Error {
+        Error::InvalidArgument { argument, reason: reason.into() }
+    }
+
+    /// Returns true if repeating the operation might succeed, e.g. after a
+    /// timeout or after reading a stale report left over from an earlier
+    /// exchange.
+    pub fn is_retryable(&self) -> bool {
+        matches!(*self,
+                 Error::Transport(TransportError::NoResponse) |
+                 Error::Transport(TransportError::Usb(libusb::Error::Timeout)) |
+                 Error::Transport(TransportError::Usb(libusb::Error::Interrupted)) |
+                 Error::Transport(TransportError::Usb(libusb::Error::Busy)) |
+                 Error::Protocol(ProtocolError::BadCommandId { .. }))
+    }
+
+    /// Returns true if the device has gone away, and must be reopened before
+    /// it can be used again.
+    pub fn is_disconnected(&self) -> bool {
+        matches!(*self,
+                 Error::Transport(TransportError::NoDevice) |
+                 Error::Transport(TransportError::Usb(libusb::Error::NoDevice)) |
+                 Error::Transport(TransportError::Usb(libusb::Error::Io)) |
+                 Error::Transport(TransportError::Usb(libusb::Error::Pipe)))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Transport(ref e) => write!(f, "Transport error: {}", e),
+            Error::Protocol(ref e) => write!(f, "Protocol error: {}", e),
+            Error::Decode(ref e) => write!(f, "Decode error: {}", e),
+            Error::UnsupportedRegister(register) => write!(f, "Unsupported register 0x{:02x}", register),
+            Error::InvalidArgument { argument, ref reason } => write!(f, "Invalid {}: {}", argument, reason),
+            Error::Sensor(ref message) => write!(f, "Sensor error: {}", message),
+            Error::Config(ref message) => write!(f, "Config error: {}", message),
+        }
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransportError::NoDevice => write!(f, "No device found"),
+            TransportError::Usb(ref e) => write!(f, "{}", e),
+            #[cfg(feature = "hidapi")]
+            TransportError::Hid(ref e) => write!(f, "{}", e),
+            TransportError::Io(ref e) => write!(f, "{}", e),
+            TransportError::NoResponse => write!(f, "No response from device"),
+            TransportError::Backend(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtocolError::BadCommandId { expected, actual } =>
+                write!(f, "Bad command ID: expected {} but got {}", expected, actual),
+            ProtocolError::BadOpcode { command_id, expected, actual } =>
+                write!(f, "Bad opcode echo for command {}: expected 0x{:02x} but got 0x{:02x}", command_id, expected, actual),
+            ProtocolError::PacketTooLarge { len } =>
+                write!(f, "Packet of {} bytes does not fit in a single report", len),
+            ProtocolError::WriteNotApplied { register, ref written, ref read } =>
+                write!(f, "Register 0x{:02x} reads back {} after writing {}", register, read, written),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::TruncatedPacket { expected, actual } =>
+                write!(f, "Truncated packet: expected at least {} bytes but got {}", expected, actual),
+            DecodeError::TruncatedValue { register, expected, actual } =>
+                write!(f, "Truncated value for register 0x{:02x}: expected {} bytes but got {}", register, expected, actual),
+            DecodeError::BadBlockLength { register, expected, actual } =>
+                write!(f, "Bad block length for register 0x{:02x}: expected {} but got {}", register, expected, actual),
+            DecodeError::InvalidByte { register, byte } =>
+                write!(f, "Invalid value 0x{:02x} for register 0x{:02x}", byte, register),
+            DecodeError::InvalidString { register, ref error } =>
+                write!(f, "Invalid string in register 0x{:02x}: {}", register, error),
+            DecodeError::MissingValue { register } =>
+                write!(f, "No value for register 0x{:02x} in the response", register),
+            DecodeError::UnexpectedValue { register, ref value } =>
+                write!(f, "Unexpected value {} for register 0x{:02x}", value, register),
+            DecodeError::UnterminatedString { register } =>
+                write!(f, "No null byte found in string register 0x{:02x}", register),
+            DecodeError::BadEcho { expected, actual } =>
+                write!(f, "Bad echo: expected flag 0x{:02x} and command 0x{:02x} but got 0x{:02x} and 0x{:02x}",
+                       expected[0], expected[1], actual[0], actual[1]),
+            DecodeError::UnknownCommand(command) => write!(f, "Unknown command 0x{:02x}", command),
+            DecodeError::InvalidCapture(ref message) => write!(f, "Invalid capture: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Transport(ref e) => Some(e),
+            Error::Protocol(ref e) => Some(e),
+            Error::Decode(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl error::Error for TransportError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            TransportError::Usb(ref e) => Some(e),
+            #[cfg(feature = "hidapi")]
+            TransportError::Hid(ref e) => Some(e),
+            TransportError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl error::Error for ProtocolError {}
+
+impl error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            DecodeError::InvalidString { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransportError> for Error {
+    fn from(e: TransportError) -> Error {
+        Error::Transport(e)
+    }
+}
+
+impl From<ProtocolError> for Error {
+    fn from(e: ProtocolError) -> Error {
+        Error::Protocol(e)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Error {
+        Error::Decode(e)
+    }
+}
+
+impl From<libusb::Error> for Error {
+    fn from(e: libusb::Error) -> Error {
+        Error::Transport(TransportError::Usb(e))
+    }
+}
+
+#[cfg(feature = "hidapi")]
+impl From<hidapi::HidError> for Error {
+    fn from(e: hidapi::HidError) -> Error {
+        Error::Transport(TransportError::Hid(e))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Transport(TransportError::Io(e))
+    }
+}
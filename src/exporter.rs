@@ -0,0 +1,287 @@
+//! # Prometheus exporter
+//!
+//! Serves a cooler's temperatures, fan speeds and modes in the Prometheus text
+//! exposition format over plain HTTP. The device is polled on a fixed
+//! interval, and every scrape is answered from the most recent poll so that
+//! scrapes never wait on USB.
+//!
+//! On coolers with a pump, the fan channel that drives it is reported as
+//! `corsairlink_pump_rpm` rather than as a fan. Devices without one, such as
+//! the Commander Mini, have every channel reported as a fan.
+//!
+//! The server is deliberately minimal: it runs on the calling thread, handles
+//! one connection at a time and only answers `GET /metrics`. It should be
+//! bound to localhost, or placed behind a proxy if it must be reachable from
+//! elsewhere.
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use backends::Backend;
+use devices::cooler::h110i::Device;
+
+pub const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:9769";
+
+const METRICS_PATH: &str = "/metrics";
+const MAX_REQUEST_SIZE: usize = 8192;
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Exporter<B: Backend> {
+    device: Device<B>,
+    labels: Vec<(String, String)>,
+    metrics: String,
+}
+
+/// Escapes a label value as required by the exposition format.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl<B: Backend> Exporter<B> {
+    /// Exports metrics for `device`, which must have had its metadata read.
+    /// Its product name and firmware version are added to every metric as
+    /// labels.
+    pub fn new(device: Device<B>) -> Exporter<B> {
+        let labels = vec![
+            ("product".to_string(), device.product_name().to_string()),
+            ("firmware".to_string(), device.firmware_version().to_string()),
+        ];
+        Exporter { device, labels, metrics: String::new() }
+    }
+
+    /// Adds a label to every metric, such as the device's USB bus path.
+    pub fn label<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Exporter<B> {
+        self.labels.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn device(&self) -> &Device<B> {
+        &self.device
+    }
+
+    /// Returns the metrics rendered by the last poll.
+    pub fn metrics(&self) -> &str {
+        &self.metrics
+    }
+
+    fn poll_device(&mut self) -> Result<()> {
+        self.device.poll_temperatures()?;
+        self.device.poll_fans()?;
+        self.device.poll_leds()?;
+        Ok(())
+    }
+
+    fn labels(&self, extra: &[(&str, String)]) -> String {
+        let labels: Vec<String> = self.labels.iter().map(|(name, value)| (&name[..], value))
+            .chain(extra.iter().map(|&(name, ref value)| (name, value)))
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_label(value)))
+            .collect();
+        format!("{{{}}}", labels.join(","))
+    }
+
+    /// Polls the device and renders its state. If polling fails, only
+    /// `corsairlink_up` is rendered, with the value 0.
+    pub fn poll(&mut self) -> Result<()> {
+        let result = self.poll_device();
+
+        let mut out = String::new();
+        let none = self.labels(&[]);
+        let _ = writeln!(out, "# HELP corsairlink_up Whether the last poll of the device succeeded.");
+        let _ = writeln!(out, "# TYPE corsairlink_up gauge");
+        let _ = writeln!(out, "corsairlink_up{} {}", none, if result.is_ok() { 1 } else { 0 });
+
+        if let Err(e) = result {
+            warn!("Failed to poll the device: {}", e);
+            self.metrics = out;
+            return Err(e);
+        }
+
+        let device = &self.device;
+        let pump = device.pump_channel().map(usize::from);
+
+        let _ = writeln!(out, "# HELP corsairlink_temperature_celsius Temperature reported by a sensor.");
+        let _ = writeln!(out, "# TYPE corsairlink_temperature_celsius gauge");
+        for (i, temperature) in device.temperatures.iter().enumerate() {
+            let _ = writeln!(out, "corsairlink_temperature_celsius{} {}",
+                             self.labels(&[("sensor", i.to_string())]), temperature.degrees_c());
+        }
+
+        let _ = writeln!(out, "# HELP corsairlink_fan_rpm Fan speed.");
+        let _ = writeln!(out, "# TYPE corsairlink_fan_rpm gauge");
+        for (i, rpm) in device.fan_speeds.iter().enumerate().filter(|&(i, _)| Some(i) != pump) {
+            let _ = writeln!(out, "corsairlink_fan_rpm{} {}", self.labels(&[("fan", i.to_string())]), rpm);
+        }
+
+        if let Some(rpm) = pump.and_then(|pump| device.fan_speeds.get(pump)) {
+            let _ = writeln!(out, "# HELP corsairlink_pump_rpm Pump speed.");
+            let _ = writeln!(out, "# TYPE corsairlink_pump_rpm gauge");
+            let _ = writeln!(out, "corsairlink_pump_rpm{} {}", none, rpm);
+        }
+
+        let _ = writeln!(out, "# HELP corsairlink_fan_profile The profile each fan channel is running, as a label.");
+        let _ = writeln!(out, "# TYPE corsairlink_fan_profile gauge");
+        for (i, mode) in device.fan_modes.iter().enumerate() {
+            let channel = if Some(i) == pump { "pump".to_string() } else { i.to_string() };
+            let _ = writeln!(out, "corsairlink_fan_profile{} 1",
+                             self.labels(&[("fan", channel), ("profile", format!("{:?}", mode.profile()))]));
+        }
+
+        let _ = writeln!(out, "# HELP corsairlink_led_mode The mode each LED is in, as a label.");
+        let _ = writeln!(out, "# TYPE corsairlink_led_mode gauge");
+        for (i, mode) in device.led_modes.iter().enumerate() {
+            let _ = writeln!(out, "corsairlink_led_mode{} 1",
+                             self.labels(&[("led", i.to_string()), ("mode", format!("{:?}", mode))]));
+        }
+
+        self.metrics = out;
+        Ok(())
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+        stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let request = String::from_utf8_lossy(&request);
+        let mut words = request.split_whitespace();
+        let (status, content_type, body) = match (words.next(), words.next()) {
+            (Some("GET"), Some(METRICS_PATH)) => ("200 OK", "text/plain; version=0.0.4", &self.metrics[..]),
+            (Some("GET"), Some(_)) => ("404 Not Found", "text/plain", "Not found\n"),
+            _ => ("405 Method Not Allowed", "text/plain", "Method not allowed\n"),
+        };
+
+        write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+               status, content_type, body.len(), body)?;
+        stream.flush()
+    }
+
+    /// Polls the device every `interval` and answers scrapes on `address`
+    /// for as long as `keep_running` returns true. A failed poll is reported
+    /// through `corsairlink_up` rather than stopping the exporter.
+    pub fn serve<A: ToSocketAddrs, F: FnMut() -> bool>(&mut self, address: A, interval: Duration, keep_running: F) -> Result<()> {
+        let listener = TcpListener::bind(address)?;
+        self.serve_listener(listener, interval, keep_running)
+    }
+
+    /// Like `serve`, but on a listener that is already bound.
+    pub fn serve_listener<F: FnMut() -> bool>(&mut self, listener: TcpListener, interval: Duration, mut keep_running: F) -> Result<()> {
+        listener.set_nonblocking(true)?;
+        info!("Serving metrics on http://{}{}", listener.local_addr()?, METRICS_PATH);
+
+        let mut next_poll = Instant::now();
+        while keep_running() {
+            if Instant::now() >= next_poll {
+                let _ = self.poll();
+                next_poll = Instant::now() + interval;
+            }
+
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    if let Err(e) = self.respond(stream) {
+                        debug!("Failed to answer {}: {}", peer, e);
+                    }
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Shutdown, SocketAddr};
+    use std::sync::mpsc;
+    use backends::simulator::Simulator;
+    use devices::cooler::h110i::CoolerModel;
+
+    fn exporter(simulator: Simulator) -> Exporter<Simulator> {
+        let mut device = Device::new(simulator);
+        device.get_metadata().unwrap();
+        Exporter::new(device)
+    }
+
+    fn scrape(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    /// Serves `exporter` on an ephemeral port until `path` has been scraped,
+    /// returning the response.
+    fn serve_scrape(exporter: &mut Exporter<Simulator>, path: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let client = thread::spawn(move || tx.send(scrape(address, path)).unwrap());
+
+        let mut response = None;
+        exporter.serve_listener(listener, Duration::from_secs(60), || {
+            if let Ok(r) = rx.try_recv() {
+                response = Some(r);
+            }
+            response.is_none()
+        }).unwrap();
+        client.join().unwrap();
+        response.unwrap()
+    }
+
+    #[test]
+    fn scrape_metrics() {
+        let mut exporter = exporter(Simulator::new()).label("bus_path", "1-4.2");
+        let response = serve_scrape(&mut exporter, "/metrics");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let labels = "product=\"H110i\",firmware=\"2.0.00\",bus_path=\"1-4.2\"";
+        for line in &[
+            format!("corsairlink_up{{{}}} 1", labels),
+            format!("corsairlink_temperature_celsius{{{},sensor=\"0\"}} 30.5", labels),
+            format!("corsairlink_fan_rpm{{{},fan=\"0\"}} 1100", labels),
+            format!("corsairlink_fan_rpm{{{},fan=\"1\"}} 1150", labels),
+            format!("corsairlink_pump_rpm{{{}}} 2100", labels),
+            format!("corsairlink_fan_profile{{{},fan=\"pump\",profile=\"ProfileDefault\"}} 1", labels),
+            format!("corsairlink_led_mode{{{},led=\"0\",mode=\"Static\"}} 1", labels),
+        ] {
+            assert!(response.lines().any(|l| l == line), "missing {:?} in {}", line, response);
+        }
+        assert!(!response.contains("fan=\"2\""));
+    }
+
+    #[test]
+    fn scrape_unknown_path() {
+        let mut exporter = exporter(Simulator::new());
+        assert!(serve_scrape(&mut exporter, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn device_without_pump() {
+        let mut exporter = exporter(Simulator::for_model(CoolerModel::CommanderMini));
+        exporter.poll().unwrap();
+        let metrics = exporter.metrics();
+
+        assert!(!metrics.contains("corsairlink_pump_rpm"));
+        assert!(!metrics.contains("fan=\"pump\""));
+        assert_eq!(metrics.lines().filter(|l| l.starts_with("corsairlink_fan_rpm{")).count(), 6);
+    }
+}
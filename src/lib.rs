@@ -1,25 +1,22 @@
-#![recursion_limit = "1024"]
-
-#[macro_use]
-extern crate error_chain;
-
 extern crate byteorder;
 extern crate hex_slice;
 
 extern crate libusb;
+#[cfg(feature = "hidapi")]
+extern crate hidapi;
+#[macro_use]
+extern crate log;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
 
-pub mod errors {
-    use std::string;
-    use libusb;
-
-    error_chain! {
-        foreign_links {
-            String(string::FromUtf8Error) #[doc = "Error parsing UTF-8 string"];
-            Libusb(libusb::Error) #[doc = "Error from libusb"];
-        }
-    }
-}
+pub mod errors;
 
-mod backends;
-mod protocol;
+pub mod backends;
+pub mod protocol;
 pub mod devices;
+pub mod sensors;
+pub mod control;
+pub mod exporter;
+pub mod config;
@@ -1,44 +1,828 @@
-#[macro_use]
-extern crate error_chain;
-
 extern crate corsairlink;
+#[macro_use]
+extern crate clap;
+extern crate env_logger;
 extern crate libusb;
+extern crate serde;
+#[macro_use]
+extern crate serde_json;
+
+use std::fs;
+use std::io;
+use std::process;
+use std::thread;
+use std::time::Duration;
 
-use corsairlink::devices::cooler::h110i;
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
+use serde::Serialize;
+
+use corsairlink::backends::Backend;
+use corsairlink::backends::asetek_simulator::AsetekSimulator;
+use corsairlink::backends::psu_simulator::PsuSimulator;
+use corsairlink::backends::simulator::Simulator;
+use corsairlink::backends::usbhid;
+use corsairlink::config::{Config, DeviceConfig};
+use corsairlink::devices::{Model, ASETEK_MODELS};
+use corsairlink::devices::cooler::{asetek, h110i};
+use corsairlink::devices::psu::hx750i;
+use corsairlink::devices::cooler::h110i::{FanCurve, FanProfile, LedMode, RgbColor, TempChannel, Temperature, TemperatureGradient};
 use corsairlink::errors::*;
+use corsairlink::exporter::{self, Exporter};
+use corsairlink::protocol::usbhid::Value;
+
+fn main() {
+    env_logger::init();
+
+    if let Err(e) = run(&app().get_matches()) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    let fan = Arg::with_name("fan")
+        .help("Fan number, starting from 0")
+        .required(true);
+
+    App::new("corsairlink")
+        .version(crate_version!())
+        .about("Monitors and configures Corsair Link devices")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(Arg::with_name("device")
+             .long("device").short("d")
+             .value_name("INDEX")
+             .global(true)
+             .help("Uses the device numbered INDEX by `list` [default: the first of the right model]"))
+        .arg(Arg::with_name("bus")
+             .long("bus")
+             .value_name("BUS:ADDRESS")
+             .global(true)
+             .help("Uses the device at a USB bus and address"))
+        .arg(Arg::with_name("port")
+             .long("port")
+             .value_name("PORT")
+             .global(true)
+             .help("Uses the device plugged into a USB port, such as 1-4.2"))
+        .arg(Arg::with_name("format")
+             .long("format")
+             .takes_value(true)
+             .possible_values(&["text", "json"])
+             .default_value("text")
+             .global(true)
+             .help("Output format for info, status, watch, psu status and asetek status"))
+        .arg(Arg::with_name("simulate")
+             .long("simulate")
+             .global(true)
+             .help("Uses a simulated H110i, HX750i or Asetek-based cooler instead of real hardware"))
+        .subcommand(SubCommand::with_name("list")
+                    .about("Lists supported devices"))
+        .subcommand(SubCommand::with_name("info")
+                    .about("Shows the device's model, firmware and channels"))
+        .subcommand(SubCommand::with_name("status")
+                    .about("Shows temperatures, fan speeds and LED settings"))
+        .subcommand(SubCommand::with_name("watch")
+                    .about("Shows the status repeatedly")
+                    .arg(Arg::with_name("interval")
+                         .long("interval").short("n")
+                         .value_name("SECONDS")
+                         .default_value("1")
+                         .help("Time between updates")))
+        .subcommand(SubCommand::with_name("exporter")
+                    .about("Serves metrics to Prometheus")
+                    .arg(Arg::with_name("listen")
+                         .long("listen")
+                         .value_name("ADDRESS")
+                         .default_value(exporter::DEFAULT_LISTEN_ADDRESS)
+                         .help("Address to serve /metrics on"))
+                    .arg(Arg::with_name("interval")
+                         .long("interval").short("n")
+                         .value_name("SECONDS")
+                         .default_value("5")
+                         .help("Time between polls of the device")))
+        .subcommand(SubCommand::with_name("led")
+                    .about("Configures the LED")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("set")
+                                .about("Sets the LED's mode and colors")
+                                .arg(Arg::with_name("mode")
+                                     .long("mode")
+                                     .takes_value(true)
+                                     .possible_values(&["static", "two-color-cycle", "four-color-cycle", "temperature"]))
+                                .arg(Arg::with_name("speed")
+                                     .long("speed")
+                                     .takes_value(true)
+                                     .default_value("7")
+                                     .help("Color cycle speed, from 0 to 15"))
+                                .arg(Arg::with_name("channel")
+                                     .long("channel")
+                                     .takes_value(true)
+                                     .possible_values(&["internal", "manual"])
+                                     .default_value("internal")
+                                     .help("Temperature followed in temperature mode"))
+                                .arg(Arg::with_name("colors")
+                                     .long("colors")
+                                     .value_name("RRGGBB,...")
+                                     .help("Up to four colors to cycle between"))
+                                .arg(Arg::with_name("gradient")
+                                     .long("gradient")
+                                     .value_name("TEMP:RRGGBB,...")
+                                     .help("Three temperatures and the colors shown at them in temperature mode"))
+                                .group(ArgGroup::with_name("settings")
+                                       .args(&["mode", "colors", "gradient"])
+                                       .multiple(true)
+                                       .required(true))))
+        .subcommand(SubCommand::with_name("fan")
+                    .about("Configures fans")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("set")
+                                .about("Sets a fan's profile or fixed speed")
+                                .arg(fan.clone())
+                                .arg(Arg::with_name("profile")
+                                     .long("profile")
+                                     .takes_value(true)
+                                     .possible_values(&["default", "quiet", "balanced", "performance", "custom"]))
+                                .arg(Arg::with_name("pwm")
+                                     .long("pwm")
+                                     .value_name("PERCENT")
+                                     .help("Runs the fan at a fixed duty cycle"))
+                                .arg(Arg::with_name("rpm")
+                                     .long("rpm")
+                                     .value_name("RPM")
+                                     .help("Runs the fan at a fixed speed"))
+                                .group(ArgGroup::with_name("setting")
+                                       .args(&["profile", "pwm", "rpm"])
+                                       .required(true)))
+                    .subcommand(SubCommand::with_name("curve")
+                                .about("Shows or sets the curve used by a fan's custom profile")
+                                .arg(fan)
+                                .arg(Arg::with_name("points")
+                                     .long("points")
+                                     .value_name("TEMP:RPM,...")
+                                     .help("Five points to store, switching the fan to its custom profile"))))
+        .subcommand(SubCommand::with_name("apply")
+                    .about("Applies a configuration file, writing only the settings that differ")
+                    .arg(Arg::with_name("file")
+                         .value_name("FILE")
+                         .required(true)
+                         .help("TOML file describing the desired state of each device"))
+                    .arg(Arg::with_name("dry-run")
+                         .long("dry-run")
+                         .help("Shows the changes without making them")))
+        .subcommand(SubCommand::with_name("psu")
+                    .about("Monitors and configures an HX750i power supply")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("status")
+                                .about("Shows voltages, currents, power, temperatures and uptime"))
+                    .subcommand(SubCommand::with_name("fan")
+                                .about("Sets how the PSU's fan is driven")
+                                .arg(Arg::with_name("hardware")
+                                     .long("hardware")
+                                     .help("Lets the firmware drive the fan"))
+                                .arg(Arg::with_name("duty")
+                                     .long("duty")
+                                     .value_name("PERCENT")
+                                     .help("Runs the fan at a fixed duty cycle, from 30 to 100"))
+                                .group(ArgGroup::with_name("mode")
+                                       .args(&["hardware", "duty"])
+                                       .required(true)))
+                    .subcommand(SubCommand::with_name("ocp")
+                                .about("Shows or sets over-current protection")
+                                .arg(Arg::with_name("mode")
+                                     .long("mode")
+                                     .takes_value(true)
+                                     .possible_values(&["single", "multi"])
+                                     .help("Protects the +12V output as a single rail or each rail separately"))
+                                .arg(Arg::with_name("limits")
+                                     .long("limits")
+                                     .value_name("RAIL:AMPS,...")
+                                     .help("Per-rail limits used in multi-rail mode, e.g. 12V:40,5V:20"))))
+        .subcommand(SubCommand::with_name("asetek")
+                    .about("Monitors and configures an Asetek-based cooler, such as the H100i v2")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("status")
+                                .about("Shows the liquid temperature, fan and pump speeds, and firmware version"))
+                    .subcommand(SubCommand::with_name("color")
+                                .about("Sets the LED's color, and the color it shows when the liquid gets too hot")
+                                .arg(Arg::with_name("color")
+                                     .value_name("RRGGBB")
+                                     .required(true))
+                                .arg(Arg::with_name("alert-color")
+                                     .long("alert-color")
+                                     .value_name("RRGGBB")
+                                     .default_value("ff0000"))
+                                .arg(Arg::with_name("alert-temperature")
+                                     .long("alert-temperature")
+                                     .value_name("TEMP")
+                                     .default_value("60")
+                                     .help("Liquid temperature at which the alert color is shown")))
+                    .subcommand(SubCommand::with_name("fan")
+                                .about("Sets the fan's fixed duty cycle or curve")
+                                .arg(Arg::with_name("duty")
+                                     .long("duty")
+                                     .value_name("PERCENT")
+                                     .help("Runs the fan at a fixed duty cycle"))
+                                .arg(Arg::with_name("curve")
+                                     .long("curve")
+                                     .value_name("TEMP:PERCENT,...")
+                                     .help("Six points of liquid temperature and duty cycle"))
+                                .group(ArgGroup::with_name("setting")
+                                       .args(&["duty", "curve"])
+                                       .required(true)))
+                    .subcommand(SubCommand::with_name("pump")
+                                .about("Sets the pump's mode")
+                                .arg(Arg::with_name("mode")
+                                     .possible_values(&["quiet", "balanced", "performance"])
+                                     .required(true))))
+        .subcommand(SubCommand::with_name("snapshot")
+                    .about("Saves or restores every register of the device")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("save")
+                                .about("Saves a snapshot to a JSON file")
+                                .arg(Arg::with_name("file").value_name("FILE").required(true)))
+                    .subcommand(SubCommand::with_name("restore")
+                                .about("Writes the settings in a snapshot back to the device")
+                                .arg(Arg::with_name("file").value_name("FILE").required(true))))
+        .subcommand(SubCommand::with_name("raw")
+                    .about("Reads and writes registers directly")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("read")
+                                .about("Reads a register")
+                                .arg(Arg::with_name("register").required(true)))
+                    .subcommand(SubCommand::with_name("write")
+                                .about("Writes hex bytes to a register")
+                                .arg(Arg::with_name("register").required(true))
+                                .arg(Arg::with_name("data").required(true).multiple(true))))
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+fn run(matches: &ArgMatches) -> Result<()> {
+    if matches.subcommand_name() == Some("list") {
+        let context = libusb::Context::new()?;
+        return list(&context);
+    }
+
+    if let ("apply", Some(apply_matches)) = matches.subcommand() {
+        return apply(apply_matches, matches.is_present("simulate"));
+    }
+
+    if let ("psu", Some(psu_matches)) = matches.subcommand() {
+        return psu(matches, psu_matches);
+    }
 
-quick_main!(run);
+    if let ("asetek", Some(asetek_matches)) = matches.subcommand() {
+        return asetek(matches, asetek_matches);
+    }
 
-fn run() -> Result<()> {
-    let context = libusb::Context::new().unwrap();
+    if matches.is_present("simulate") {
+        let mut cooler = h110i::Device::new(Simulator::new());
+        if let ("exporter", Some(matches)) = matches.subcommand() {
+            return serve_metrics(cooler, matches, "simulator".to_string());
+        }
+        return command(&mut cooler, matches);
+    }
 
-    let mut cooler = h110i::Device::open(&context)?;
+    let context = libusb::Context::new()?;
+    let selector = selector(matches, usbhid::Selector::Model(Model::LinkHid))?;
+    if let ("exporter", Some(matches)) = matches.subcommand() {
+        let bus_path = usbhid::devices(&context)?.into_iter().enumerate()
+            .find(|&(i, ref info)| selector.matches(i, info))
+            .map_or(String::new(), |(_, info)| match info.port_path {
+                Some(port_path) => port_path,
+                None => format!("{}:{}", info.bus, info.address),
+            });
+        return serve_metrics(h110i::Device::open_selected(&context, &selector)?, matches, bus_path);
+    }
+
+    let mut cooler = h110i::Device::open_selected(&context, &selector)?;
+    command(&mut cooler, matches)
+}
+
+/// Picks the device chosen on the command line, or else the one picked by
+/// `default`.
+fn selector(matches: &ArgMatches, default: usbhid::Selector) -> Result<usbhid::Selector> {
+    if let Some(port) = matches.value_of("port") {
+        return Ok(usbhid::Selector::PortPath(port.to_string()));
+    }
+
+    if let Some(bus) = matches.value_of("bus") {
+        let parts: Vec<&str> = bus.splitn(2, ':').collect();
+        if parts.len() != 2 {
+            return Err(Error::invalid_argument("bus", format!("expected BUS:ADDRESS but got {:?}", bus)));
+        }
+        return Ok(usbhid::Selector::BusAddress(parse_u8("bus", parts[0])?, parse_u8("address", parts[1])?));
+    }
+
+    match matches.value_of("device") {
+        Some(index) => match index.parse() {
+            Ok(index) => Ok(usbhid::Selector::Index(index)),
+            Err(_) => Err(Error::invalid_argument("device", format!("{:?} is not a device number", index))),
+        },
+        None => Ok(default),
+    }
+}
+
+fn list(context: &libusb::Context) -> Result<()> {
+    for (i, info) in usbhid::devices(context)?.iter().enumerate() {
+        println!("{}: {} on bus {:03} address {:03}{}{}{}",
+                 i, info.model.name(), info.bus, info.address,
+                 info.port_path.as_ref().map_or(String::new(), |port| format!(", port {}", port)),
+                 info.product_name.as_ref().map_or(String::new(), |name| format!(", {}", name)),
+                 info.serial_number.as_ref().map_or(String::new(), |serial| format!(", serial {}", serial)));
+    }
+    Ok(())
+}
+
+fn command<B: Backend>(cooler: &mut h110i::Device<B>, matches: &ArgMatches) -> Result<()> {
     cooler.get_metadata()?;
-    println!("Cooler: {:?}", cooler);
 
-    cooler.poll_leds()?;
+    let format = format(matches);
+    match matches.subcommand() {
+        ("info", Some(_)) => info(cooler, format),
+        ("status", Some(_)) => status(cooler, format, true),
+        ("watch", Some(matches)) => watch(cooler, matches, format),
+        ("led", Some(matches)) => match matches.subcommand() {
+            ("set", Some(matches)) => led_set(cooler, matches),
+            _ => unreachable!(),
+        },
+        ("fan", Some(matches)) => match matches.subcommand() {
+            ("set", Some(matches)) => fan_set(cooler, matches),
+            ("curve", Some(matches)) => fan_curve(cooler, matches),
+            _ => unreachable!(),
+        },
+        ("snapshot", Some(matches)) => match matches.subcommand() {
+            ("save", Some(matches)) => snapshot_save(cooler, matches),
+            ("restore", Some(matches)) => snapshot_restore(cooler, matches),
+            _ => unreachable!(),
+        },
+        ("raw", Some(matches)) => match matches.subcommand() {
+            ("read", Some(matches)) => raw_read(cooler, matches),
+            ("write", Some(matches)) => raw_write(cooler, matches),
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn format(matches: &ArgMatches) -> Format {
+    match matches.value_of("format") {
+        Some("json") => Format::Json,
+        _ => Format::Text,
+    }
+}
+
+/// Prints a value as JSON, either pretty-printed or on a single line.
+fn print_json<T: Serialize>(value: &T, pretty: bool) -> Result<()> {
+    let json = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    };
+    println!("{}", json.map_err(io::Error::from)?);
+    Ok(())
+}
+
+fn info<B: Backend>(cooler: &mut h110i::Device<B>, format: Format) -> Result<()> {
+    if format == Format::Json {
+        return print_json(&json!({
+            "device_id": cooler.device_id(),
+            "model": cooler.model(),
+            "firmware_version": cooler.firmware_version(),
+            "product_name": cooler.product_name(),
+            "led_count": cooler.led_count(),
+            "temp_sensor_count": cooler.temp_sensor_count(),
+            "fan_count": cooler.fan_count(),
+        }), true);
+    }
+
+    println!("Product: {}", cooler.product_name());
+    if let Some(model) = cooler.model() {
+        println!("Model: {}", model);
+    }
+    println!("Device ID: 0x{:02x}", cooler.device_id());
+    println!("Firmware: {}", cooler.firmware_version());
+    println!("LEDs: {}", cooler.led_count());
+    println!("Temperature sensors: {}", cooler.temp_sensor_count());
+    println!("Fans: {}", cooler.fan_count());
+    Ok(())
+}
+
+fn status<B: Backend>(cooler: &mut h110i::Device<B>, format: Format, pretty: bool) -> Result<()> {
     cooler.poll_temperatures()?;
     cooler.poll_fans()?;
-
-    println!("Temperature: {}", cooler.temperatures[0]);
-    println!("Fan Speeds: {}, {}, {}", cooler.fan_speeds[0], cooler.fan_speeds[1], cooler.fan_speeds[2]);
-    println!("Fan Modes: {:?}", cooler.fan_modes);
-    println!("LED Modes: {:?}", cooler.led_modes[0]);
-    println!("LED Colors: {:?}", cooler.led_colors[0]);
-    println!("LED Cycle Colors: {:?}", cooler.led_cycle_colors[0]);
-
-    println!("");
-    println!("Setting color cycle to magenta, green, blue, white");
-    cooler.set_led_colors(0, [
-        h110i::RgbColor(255, 0, 255),
-        h110i::RgbColor(0, 255, 0),
-        h110i::RgbColor(0, 0, 255),
-        h110i::RgbColor(255, 255, 255),
-    ])?;
-    cooler.set_led_mode(h110i::LedMode::four_color_cycle_mode(7))?;
     cooler.poll_leds()?;
-    println!("LED Colors: {:?}", cooler.led_colors[0]);
-    println!("LED Cycle Colors: {:?}", cooler.led_cycle_colors[0]);
 
+    if format == Format::Json {
+        return print_json(&cooler.status(), pretty);
+    }
+
+    for (i, temperature) in cooler.temperatures.iter().enumerate() {
+        println!("Temperature {}: {}", i, temperature);
+    }
+    for (i, (rpm, mode)) in cooler.fan_speeds.iter().zip(cooler.fan_modes.iter()).enumerate() {
+        println!("Fan {}: {} RPM, {:?}", i, rpm, mode.profile());
+    }
+    for (i, (mode, color)) in cooler.led_modes.iter().zip(cooler.led_colors.iter()).enumerate() {
+        println!("LED {}: {:?}, showing {}", i, mode, color);
+    }
+    Ok(())
+}
+
+fn serve_metrics<B: Backend>(mut cooler: h110i::Device<B>, matches: &ArgMatches, bus_path: String) -> Result<()> {
+    let interval = parse_interval(matches)?;
+    cooler.get_metadata()?;
+
+    let mut exporter = Exporter::new(cooler).label("bus_path", bus_path);
+    exporter.serve(matches.value_of("listen").unwrap(), interval, || true)
+}
+
+fn parse_interval(matches: &ArgMatches) -> Result<Duration> {
+    let interval = value_t!(matches, "interval", f64)
+        .map_err(|e| Error::invalid_argument("interval", e.message))?;
+    if interval.is_nan() || interval <= 0.0 {
+        return Err(Error::invalid_argument("interval", "must be more than zero"));
+    }
+    Ok(Duration::from_millis((interval * 1000.0) as u64))
+}
+
+/// Prints the status every interval. JSON output is written one object per
+/// line so that it can be streamed into other tools.
+fn watch<B: Backend>(cooler: &mut h110i::Device<B>, matches: &ArgMatches, format: Format) -> Result<()> {
+    let interval = parse_interval(matches)?;
+
+    loop {
+        status(cooler, format, false)?;
+        if format == Format::Text {
+            println!();
+        }
+        thread::sleep(interval);
+    }
+}
+
+fn led_set<B: Backend>(cooler: &mut h110i::Device<B>, matches: &ArgMatches) -> Result<()> {
+    if let Some(colors) = matches.value_of("colors") {
+        let colors = colors.split(',').map(str::parse).collect::<Result<Vec<RgbColor>>>()?;
+        if colors.len() > 4 {
+            return Err(Error::invalid_argument("colors", "at most four colors can be cycled"));
+        }
+        cooler.set_led_colors(0, [colors[0], colors[1 % colors.len()], colors[2 % colors.len()], colors[3 % colors.len()]])?;
+    }
+
+    if let Some(gradient) = matches.value_of("gradient") {
+        let points = parse_points("gradient", gradient, str::parse)?;
+        if points.len() != 3 {
+            return Err(Error::invalid_argument("gradient", "exactly three points are needed"));
+        }
+        cooler.set_led_gradient(0, &TemperatureGradient::new([points[0], points[1], points[2]])?)?;
+    }
+
+    if let Some(mode) = matches.value_of("mode") {
+        let speed = parse_u8("speed", matches.value_of("speed").unwrap())?;
+        if speed > 0x0f {
+            return Err(Error::invalid_argument("speed", "must be from 0 to 15"));
+        }
+        let channel = match matches.value_of("channel") {
+            Some("manual") => TempChannel::Manual,
+            _ => TempChannel::InternalSensor,
+        };
+
+        cooler.set_led_mode(match mode {
+            "static" => LedMode::static_mode(),
+            "two-color-cycle" => LedMode::two_color_cycle_mode(speed),
+            "four-color-cycle" => LedMode::four_color_cycle_mode(speed),
+            "temperature" => LedMode::temperature_mode(channel),
+            _ => unreachable!(),
+        })?;
+    }
+
+    Ok(())
+}
+
+fn fan_set<B: Backend>(cooler: &mut h110i::Device<B>, matches: &ArgMatches) -> Result<()> {
+    let fan = parse_u8("fan", matches.value_of("fan").unwrap())?;
+
+    if let Some(percent) = matches.value_of("pwm") {
+        return cooler.set_fan_pwm(fan, parse_u8("pwm", percent)?);
+    }
+
+    if let Some(rpm) = matches.value_of("rpm") {
+        return match rpm.parse() {
+            Ok(rpm) => cooler.set_fan_rpm(fan, rpm),
+            Err(_) => Err(Error::invalid_argument("rpm", format!("{:?} is not a speed", rpm))),
+        };
+    }
+
+    let profile = match matches.value_of("profile") {
+        Some("quiet") => FanProfile::ProfileQuiet,
+        Some("balanced") => FanProfile::ProfileBalanced,
+        Some("performance") => FanProfile::ProfilePerformance,
+        Some("custom") => FanProfile::Custom,
+        _ => FanProfile::ProfileDefault,
+    };
+    cooler.set_fan_mode(fan, profile)
+}
+
+fn fan_curve<B: Backend>(cooler: &mut h110i::Device<B>, matches: &ArgMatches) -> Result<()> {
+    let fan = parse_u8("fan", matches.value_of("fan").unwrap())?;
+
+    if let Some(points) = matches.value_of("points") {
+        let points = parse_points("points", points, |rpm| match rpm.parse() {
+            Ok(rpm) => Ok(rpm),
+            Err(_) => Err(Error::invalid_argument("points", format!("{:?} is not a speed", rpm))),
+        })?;
+        if points.len() != 5 {
+            return Err(Error::invalid_argument("points", "exactly five points are needed"));
+        }
+        return cooler.set_fan_curve(fan, &FanCurve::new([points[0], points[1], points[2], points[3], points[4]])?);
+    }
+
+    for (temperature, rpm) in cooler.get_fan_curve(fan)?.points() {
+        println!("{}: {} RPM", temperature, rpm);
+    }
+    Ok(())
+}
+
+fn snapshot_save<B: Backend>(cooler: &mut h110i::Device<B>, matches: &ArgMatches) -> Result<()> {
+    let snapshot = cooler.snapshot()?;
+    let json = serde_json::to_string_pretty(&snapshot).map_err(io::Error::from)?;
+    fs::write(matches.value_of("file").unwrap(), json + "\n")?;
+    Ok(())
+}
+
+fn snapshot_restore<B: Backend>(cooler: &mut h110i::Device<B>, matches: &ArgMatches) -> Result<()> {
+    let file = matches.value_of("file").unwrap();
+    let json = fs::read_to_string(file)?;
+    let snapshot = serde_json::from_str(&json)
+        .map_err(|e| Error::invalid_argument("snapshot", format!("{}: {}", file, e)))?;
+    cooler.restore_snapshot(&snapshot)
+}
+
+fn raw_read<B: Backend>(cooler: &mut h110i::Device<B>, matches: &ArgMatches) -> Result<()> {
+    let register = parse_register(matches.value_of("register").unwrap())?;
+    println!("{:?}", cooler.read_register(register)?);
+    Ok(())
+}
+
+fn raw_write<B: Backend>(cooler: &mut h110i::Device<B>, matches: &ArgMatches) -> Result<()> {
+    let register = parse_register(matches.value_of("register").unwrap())?;
+
+    let hex: String = matches.values_of("data").unwrap().collect();
+    if hex.len() % 2 == 1 {
+        return Err(Error::invalid_argument("data", "expected an even number of hex digits"));
+    }
+    let data = (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16)
+             .map_err(|_| Error::invalid_argument("data", format!("{:?} is not a hex byte", &hex[i..i + 2]))))
+        .collect::<Result<Vec<u8>>>()?;
+
+    cooler.write_register(register, h110i::RegisterValue::decode(register, &data)?)
+}
+
+fn psu(matches: &ArgMatches, psu_matches: &ArgMatches) -> Result<()> {
+    if matches.is_present("simulate") {
+        return psu_command(&mut hx750i::Device::new(PsuSimulator::new()), psu_matches, format(matches));
+    }
+
+    let context = libusb::Context::new()?;
+    let mut psu = hx750i::Device::open_selected(&context, &selector(matches, usbhid::Selector::Model(Model::HX750i))?)?;
+    psu_command(&mut psu, psu_matches, format(matches))
+}
+
+fn psu_command<B: Backend>(psu: &mut hx750i::Device<B>, matches: &ArgMatches, format: Format) -> Result<()> {
+    psu.get_metadata()?;
+
+    match matches.subcommand() {
+        ("status", Some(_)) => psu_status(psu, format),
+        ("fan", Some(matches)) => psu_fan(psu, matches),
+        ("ocp", Some(matches)) => psu_ocp(psu, matches),
+        _ => unreachable!(),
+    }
+}
+
+fn format_duration(seconds: u64) -> String {
+    format!("{}d {}h {}m", seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60)
+}
+
+fn psu_status<B: Backend>(psu: &mut hx750i::Device<B>, format: Format) -> Result<()> {
+    let status = psu.poll()?;
+    if format == Format::Json {
+        return print_json(&status, true);
+    }
+
+    println!("Model: {}", status.model);
+    println!("Serial number: {}", status.serial_number);
+    println!("Input: {:.1} V, {:.1} W", status.input_voltage, status.input_power);
+    for rail in &status.rails {
+        println!("{}: {:.2} V, {:.2} A, {:.1} W", rail.rail, rail.voltage, rail.current, rail.power);
+    }
+    println!("Output: {:.1} W, {:.0}% efficient", status.output_power, status.efficiency * 100.0);
+    println!("Temperatures: {:.1}°C, {:.1}°C", status.temperatures[0], status.temperatures[1]);
+    match status.fan_mode {
+        hx750i::FanMode::Hardware => println!("Fan: {:.0} RPM, hardware controlled", status.fan_rpm),
+        hx750i::FanMode::Fixed(duty) => println!("Fan: {:.0} RPM, fixed at {}%", status.fan_rpm, duty),
+    }
+    println!("OCP: {}", format_ocp_mode(status.ocp_mode));
+    println!("Uptime: {}, {} in total", format_duration(status.uptime), format_duration(status.total_uptime));
+    Ok(())
+}
+
+fn psu_fan<B: Backend>(psu: &mut hx750i::Device<B>, matches: &ArgMatches) -> Result<()> {
+    match matches.value_of("duty") {
+        Some(duty) => psu.set_fan_mode(hx750i::FanMode::Fixed(parse_u8("duty", duty)?)),
+        None => psu.set_fan_mode(hx750i::FanMode::Hardware),
+    }
+}
+
+fn format_ocp_mode(mode: hx750i::OcpMode) -> &'static str {
+    match mode {
+        hx750i::OcpMode::SingleRail => "single rail",
+        hx750i::OcpMode::MultiRail => "multi-rail",
+    }
+}
+
+/// Sets the OCP limits and then the mode, so that switching to multi-rail
+/// never runs with stale limits, or shows the current settings if neither is
+/// given.
+fn psu_ocp<B: Backend>(psu: &mut hx750i::Device<B>, matches: &ArgMatches) -> Result<()> {
+    let mode = match matches.value_of("mode") {
+        Some("single") => Some(hx750i::OcpMode::SingleRail),
+        Some("multi") => Some(hx750i::OcpMode::MultiRail),
+        _ => None,
+    };
+    let limits = match matches.value_of("limits") {
+        Some(limits) => parse_ocp_limits(limits)?,
+        None => Vec::new(),
+    };
+
+    if mode.is_none() && limits.is_empty() {
+        println!("Mode: {}", format_ocp_mode(psu.ocp_mode()?));
+        for &rail in &hx750i::RAILS {
+            println!("{}: {:.1} A", rail, psu.ocp_limit(rail)?);
+        }
+        return Ok(());
+    }
+
+    for (rail, amps) in limits {
+        psu.set_ocp_limit(rail, amps)?;
+    }
+    if let Some(mode) = mode {
+        psu.set_ocp_mode(mode)?;
+    }
     Ok(())
 }
+
+fn asetek(matches: &ArgMatches, asetek_matches: &ArgMatches) -> Result<()> {
+    if matches.is_present("simulate") {
+        return asetek_command(&mut asetek::Device::new(AsetekSimulator::new()), asetek_matches, format(matches));
+    }
+
+    let context = libusb::Context::new()?;
+    let mut cooler = asetek::Device::open_selected(&context, &selector(matches, usbhid::Selector::Models(&ASETEK_MODELS))?)?;
+    asetek_command(&mut cooler, asetek_matches, format(matches))
+}
+
+fn asetek_command<B: Backend>(cooler: &mut asetek::Device<B>, matches: &ArgMatches, format: Format) -> Result<()> {
+    match matches.subcommand() {
+        ("status", Some(_)) => asetek_status(cooler, format),
+        ("color", Some(matches)) => {
+            let color = matches.value_of("color").unwrap().parse()?;
+            let alert_color = matches.value_of("alert-color").unwrap().parse()?;
+            let alert_temperature = parse_u8("alert-temperature", matches.value_of("alert-temperature").unwrap())?;
+            cooler.set_color(color, alert_color, alert_temperature).map(|_| ())
+        },
+        ("fan", Some(matches)) => asetek_fan(cooler, matches),
+        ("pump", Some(matches)) => {
+            let mode = match matches.value_of("mode") {
+                Some("quiet") => asetek::PumpMode::Quiet,
+                Some("performance") => asetek::PumpMode::Performance,
+                _ => asetek::PumpMode::Balanced,
+            };
+            cooler.set_pump_mode(mode).map(|_| ())
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn asetek_status<B: Backend>(cooler: &mut asetek::Device<B>, format: Format) -> Result<()> {
+    let status = cooler.get_status()?;
+    if format == Format::Json {
+        return print_json(&status, true);
+    }
+
+    println!("Liquid: {:.1}°C", status.liquid_temperature);
+    println!("Fan: {} RPM", status.fan_rpm);
+    println!("Pump: {} RPM", status.pump_rpm);
+    println!("Firmware: {}", status.firmware_version);
+    Ok(())
+}
+
+fn asetek_fan<B: Backend>(cooler: &mut asetek::Device<B>, matches: &ArgMatches) -> Result<()> {
+    if let Some(percent) = matches.value_of("duty") {
+        return cooler.set_fan_duty(parse_u8("duty", percent)?).map(|_| ());
+    }
+
+    let points = matches.value_of("curve").unwrap().split(',').map(|point| {
+        let parts: Vec<&str> = point.splitn(2, ':').collect();
+        if parts.len() != 2 {
+            return Err(Error::invalid_argument("curve", format!("expected TEMP:PERCENT but got {:?}", point)));
+        }
+        Ok((parse_u8("curve", parts[0].trim_end_matches('C'))?, parse_u8("curve", parts[1].trim_end_matches('%'))?))
+    }).collect::<Result<Vec<(u8, u8)>>>()?;
+    if points.len() != 6 {
+        return Err(Error::invalid_argument("curve", "exactly six points are needed"));
+    }
+    let curve = asetek::FanCurve::new([points[0], points[1], points[2], points[3], points[4], points[5]])?;
+    cooler.set_fan_curve(&curve).map(|_| ())
+}
+
+/// Applies each device entry in a configuration file to the device it
+/// selects, or to a fresh simulator when simulating.
+fn apply(matches: &ArgMatches, simulate: bool) -> Result<()> {
+    let config = Config::load(matches.value_of("file").unwrap())?;
+    let dry_run = matches.is_present("dry-run");
+
+    if simulate {
+        for device in &config.devices {
+            apply_device(&mut h110i::Device::new(Simulator::new()), device, dry_run)?;
+        }
+        return Ok(());
+    }
+
+    let context = libusb::Context::new()?;
+    for device in &config.devices {
+        apply_device(&mut h110i::Device::open_selected(&context, &device.selector())?, device, dry_run)?;
+    }
+    Ok(())
+}
+
+fn apply_device<B: Backend>(cooler: &mut h110i::Device<B>, config: &DeviceConfig, dry_run: bool) -> Result<()> {
+    cooler.get_metadata()?;
+    let changes = config.diff(cooler)?;
+
+    println!("{}:", cooler.product_name());
+    if changes.is_empty() {
+        println!("  No changes");
+    }
+    for change in &changes {
+        println!("  {}", change);
+        if !dry_run {
+            change.apply(cooler)?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_u8(argument: &'static str, s: &str) -> Result<u8> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| Error::invalid_argument(argument, format!("{:?} is not a number from 0 to 255", s)))
+}
+
+fn parse_rail(s: &str) -> Result<hx750i::Rail> {
+    match s.trim_start_matches('+').to_lowercase().as_str() {
+        "12v" | "12" => Ok(hx750i::Rail::V12),
+        "5v" | "5" => Ok(hx750i::Rail::V5),
+        "3.3v" | "3.3" => Ok(hx750i::Rail::V3_3),
+        _ => Err(Error::invalid_argument("limits", format!("{:?} is not a rail; expected 12V, 5V or 3.3V", s))),
+    }
+}
+
+fn parse_ocp_limits(s: &str) -> Result<Vec<(hx750i::Rail, f64)>> {
+    s.split(',').map(|limit| {
+        let parts: Vec<&str> = limit.splitn(2, ':').collect();
+        if parts.len() != 2 {
+            return Err(Error::invalid_argument("limits", format!("expected RAIL:AMPS but got {:?}", limit)));
+        }
+        let amps = parts[1].trim_end_matches('A').parse()
+            .map_err(|_| Error::invalid_argument("limits", format!("{:?} is not a current", parts[1])))?;
+        Ok((parse_rail(parts[0])?, amps))
+    }).collect()
+}
+
+fn parse_register(s: &str) -> Result<h110i::Register> {
+    let register = parse_u8("register", s)?;
+    h110i::Register::from_u8(register).ok_or(Error::UnsupportedRegister(register))
+}
+
+/// Parses a list of `TEMP:VALUE` points, with temperatures in degrees
+/// Celsius.
+fn parse_points<T, F>(argument: &'static str, s: &str, parse_value: F) -> Result<Vec<(Temperature, T)>>
+    where F: Fn(&str) -> Result<T>
+{
+    s.split(',').map(|point| {
+        let parts: Vec<&str> = point.splitn(2, ':').collect();
+        if parts.len() != 2 {
+            return Err(Error::invalid_argument(argument, format!("expected TEMP:VALUE but got {:?}", point)));
+        }
+        let degrees = parts[0].trim_end_matches("C").parse()
+            .map_err(|_| Error::invalid_argument(argument, format!("{:?} is not a temperature", parts[0])))?;
+        Ok((Temperature::from_degrees_c(degrees)?, parse_value(parts[1])?))
+    }).collect()
+}
@@ -0,0 +1,318 @@
+//! # Asetek coolers over USB bulk transfers
+//!
+//! Corsair's later coolers, such as the H80i GT, H100i v2 and H110i GT, are
+//! built on Asetek hardware and have nothing in common with the Corsair Link
+//! register protocol. They sit behind a USB bridge with a pair of bulk
+//! endpoints, and are driven by single-shot commands: the host writes one
+//! command, made of a command byte and its arguments, and the cooler answers
+//! every command with the same 32-byte status message.
+//!
+//! ```text
+//!    [0x12 0x32]
+//!      |    \----- Duty cycle: 50%
+//!      \---------- Command 0x12: FixedFan
+//!
+//!    [0x11 0x00 <6 temperatures> <6 duty cycles>]
+//!      |    \----- Always 0x00
+//!      \---------- Command 0x11: FanCurve
+//! ```
+//!
+//! Settings can't be read back; the cooler only reports its liquid
+//! temperature, fan and pump speeds, and firmware version:
+//!
+//! ```text
+//!     offset  0..2  Fan speed in RPM, big-endian
+//!     offset  8..10 Pump speed in RPM, big-endian
+//!     offset 10     Liquid temperature, whole degrees Celsius
+//!     offset 14     Liquid temperature, tenths of a degree
+//!     offset 23..27 Firmware version, one byte per component
+//! ```
+
+use hex_slice::AsHex;
+use log::Level;
+
+use errors::*;
+use backends::{Backend, WIRE_LOG_TARGET};
+
+pub const STATUS_SIZE: usize = 32;
+
+/// The number of points in a fan curve.
+pub const CURVE_POINTS: usize = 6;
+
+const FAN_RPM_OFFSET: usize = 0;
+const PUMP_RPM_OFFSET: usize = 8;
+const LIQUID_TEMPERATURE_OFFSET: usize = 10;
+const LIQUID_TEMPERATURE_TENTHS_OFFSET: usize = 14;
+const FIRMWARE_VERSION_OFFSET: usize = 0x17;
+
+/// The alert fields of a color command other than the colors and alert
+/// temperature: the blink intervals, whether the LED is lit, whether it fades
+/// or blinks between its colors, and whether the alert is enabled. Only a
+/// steady color with an alert is supported.
+const COLOR_TRAILER: [u8; 8] = [0x0a, 0x0a, 0x01, 0x00, 0x00, 0x01, 0x00, 0x01];
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Sets the LED's color, and the color it switches to when the liquid
+    /// reaches the alert temperature.
+    Color = 0x10,
+    /// Has the fan follow a curve of duty cycles against liquid temperature.
+    FanCurve = 0x11,
+    /// Runs the fan at a fixed duty cycle.
+    FixedFan = 0x12,
+    Pump = 0x13,
+    /// Changes nothing, and is only sent to read the status.
+    ReadStatus = 0x14,
+}
+
+impl Command {
+    pub fn from_u8(command: u8) -> Option<Command> {
+        match command {
+            0x10 => Some(Command::Color),
+            0x11 => Some(Command::FanCurve),
+            0x12 => Some(Command::FixedFan),
+            0x13 => Some(Command::Pump),
+            0x14 => Some(Command::ReadStatus),
+            _ => None,
+        }
+    }
+}
+
+impl From<Command> for u8 {
+    fn from(command: Command) -> u8 { command as u8 }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PumpMode {
+    Quiet = 0x00,
+    Balanced = 0x01,
+    Performance = 0x02,
+}
+
+impl PumpMode {
+    pub fn decode(data: u8) -> Option<PumpMode> {
+        match data {
+            0x00 => Some(PumpMode::Quiet),
+            0x01 => Some(PumpMode::Balanced),
+            0x02 => Some(PumpMode::Performance),
+            _ => None,
+        }
+    }
+}
+
+/// A command and its arguments. Colors are `[red, green, blue]`,
+/// temperatures are in whole degrees Celsius and duty cycles are
+/// percentages.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    Color { color: [u8; 3], alert_color: [u8; 3], alert_temperature: u8 },
+    FanCurve { temperatures: [u8; CURVE_POINTS], duties: [u8; CURVE_POINTS] },
+    FixedFan(u8),
+    Pump(PumpMode),
+    ReadStatus,
+}
+
+impl Request {
+    pub fn command(&self) -> Command {
+        match *self {
+            Request::Color { .. } => Command::Color,
+            Request::FanCurve { .. } => Command::FanCurve,
+            Request::FixedFan(_) => Command::FixedFan,
+            Request::Pump(_) => Command::Pump,
+            Request::ReadStatus => Command::ReadStatus,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![self.command().into()];
+        match *self {
+            Request::Color { color, alert_color, alert_temperature } => {
+                data.extend_from_slice(&color);
+                data.extend_from_slice(&alert_color);
+                data.push(alert_temperature);
+                data.extend_from_slice(&COLOR_TRAILER);
+            },
+            Request::FanCurve { temperatures, duties } => {
+                data.push(0x00);
+                data.extend_from_slice(&temperatures);
+                data.extend_from_slice(&duties);
+            },
+            Request::FixedFan(duty) => data.push(duty),
+            Request::Pump(mode) => data.push(mode as u8),
+            Request::ReadStatus => data.extend_from_slice(&[0x00, 0x00, 0x00]),
+        }
+        data
+    }
+
+    /// Decodes a request written by the host, as a simulated cooler would.
+    pub fn decode(data: &[u8]) -> Result<Request> {
+        let command = match data.first() {
+            Some(&command) => Command::from_u8(command).ok_or(DecodeError::UnknownCommand(command))?,
+            None => return Err(DecodeError::TruncatedPacket { expected: 1, actual: 0 }.into()),
+        };
+
+        let expected = command_len(command);
+        if data.len() < expected {
+            return Err(DecodeError::TruncatedPacket { expected, actual: data.len() }.into());
+        }
+
+        Ok(match command {
+            Command::Color => Request::Color {
+                color: [data[1], data[2], data[3]],
+                alert_color: [data[4], data[5], data[6]],
+                alert_temperature: data[7],
+            },
+            Command::FanCurve => {
+                let mut temperatures = [0u8; CURVE_POINTS];
+                let mut duties = [0u8; CURVE_POINTS];
+                temperatures.copy_from_slice(&data[2..2 + CURVE_POINTS]);
+                duties.copy_from_slice(&data[2 + CURVE_POINTS..2 + 2 * CURVE_POINTS]);
+                Request::FanCurve { temperatures, duties }
+            },
+            Command::FixedFan => Request::FixedFan(data[1]),
+            Command::Pump => Request::Pump(PumpMode::decode(data[1])
+                .ok_or(DecodeError::InvalidByte { register: command.into(), byte: data[1] })?),
+            Command::ReadStatus => Request::ReadStatus,
+        })
+    }
+}
+
+/// Returns the length of a command, including the command byte.
+fn command_len(command: Command) -> usize {
+    match command {
+        Command::Color => 8 + COLOR_TRAILER.len(),
+        Command::FanCurve => 2 + 2 * CURVE_POINTS,
+        Command::FixedFan | Command::Pump => 2,
+        Command::ReadStatus => 4,
+    }
+}
+
+/// The status message sent in response to every command.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    /// In degrees Celsius.
+    pub liquid_temperature: f64,
+    pub fan_rpm: u16,
+    pub pump_rpm: u16,
+    pub firmware_version: String,
+}
+
+impl Status {
+    pub fn decode(data: &[u8]) -> Result<Status> {
+        if data.len() < STATUS_SIZE {
+            return Err(DecodeError::TruncatedPacket { expected: STATUS_SIZE, actual: data.len() }.into());
+        }
+
+        let word = |offset: usize| u16::from(data[offset]) << 8 | u16::from(data[offset + 1]);
+        let firmware = &data[FIRMWARE_VERSION_OFFSET..FIRMWARE_VERSION_OFFSET + 4];
+        Ok(Status {
+            liquid_temperature: f64::from(data[LIQUID_TEMPERATURE_OFFSET]) + f64::from(data[LIQUID_TEMPERATURE_TENTHS_OFFSET]) / 10.0,
+            fan_rpm: word(FAN_RPM_OFFSET),
+            pump_rpm: word(PUMP_RPM_OFFSET),
+            firmware_version: format!("{}.{}.{}.{}", firmware[0], firmware[1], firmware[2], firmware[3]),
+        })
+    }
+}
+
+/// Writes a command and reads back the status message that answers it.
+pub fn exchange<B: Backend>(backend: &mut B, request: &Request) -> Result<Status> {
+    let data = request.encode();
+    let (read_timeout, write_timeout) = (backend.read_timeout(), backend.write_timeout());
+
+    if log_enabled!(target: WIRE_LOG_TARGET, Level::Trace) {
+        trace!(target: WIRE_LOG_TARGET, direction = "tx", request:? = request; "{:x}", data.as_hex());
+    }
+    backend.write_report(&data, write_timeout)?;
+
+    let mut response = [0u8; STATUS_SIZE];
+    let len = backend.read_report(&mut response, read_timeout)?;
+    trace!(target: WIRE_LOG_TARGET, direction = "rx"; "{:x}", response[..len].as_hex());
+    Status::decode(&response[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(request: Request, encoded: &[u8]) {
+        assert_eq!(request.encode(), encoded);
+        assert_eq!(Request::decode(encoded).unwrap(), request);
+    }
+
+    #[test]
+    fn color() {
+        round_trip(
+            Request::Color { color: [0xff, 0x80, 0x00], alert_color: [0xff, 0x00, 0x00], alert_temperature: 60 },
+            &[0x10, 0xff, 0x80, 0x00, 0xff, 0x00, 0x00, 0x3c, 0x0a, 0x0a, 0x01, 0x00, 0x00, 0x01, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn fan_curve() {
+        round_trip(
+            Request::FanCurve { temperatures: [25, 30, 35, 40, 45, 50], duties: [30, 40, 50, 70, 90, 100] },
+            &[0x11, 0x00, 25, 30, 35, 40, 45, 50, 30, 40, 50, 70, 90, 100]);
+    }
+
+    #[test]
+    fn fixed_fan() {
+        round_trip(Request::FixedFan(50), &[0x12, 0x32]);
+    }
+
+    #[test]
+    fn pump() {
+        round_trip(Request::Pump(PumpMode::Quiet), &[0x13, 0x00]);
+        round_trip(Request::Pump(PumpMode::Performance), &[0x13, 0x02]);
+
+        match Request::decode(&[0x13, 0x03]) {
+            Err(Error::Decode(DecodeError::InvalidByte { register: 0x13, byte: 0x03 })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn read_status() {
+        round_trip(Request::ReadStatus, &[0x14, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn decode_unknown_command() {
+        match Request::decode(&[0x42, 0x00]) {
+            Err(Error::Decode(DecodeError::UnknownCommand(0x42))) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_truncated_request() {
+        for &(data, expected) in &[(&[][..], 1), (&[0x12][..], 2), (&[0x11, 0x00, 25, 30][..], 14)] {
+            match Request::decode(data) {
+                Err(Error::Decode(DecodeError::TruncatedPacket { expected: e, actual })) if e == expected && actual == data.len() => (),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn status() {
+        let mut data = [0u8; STATUS_SIZE];
+        data[0..2].copy_from_slice(&[0x04, 0xb0]);
+        data[8..10].copy_from_slice(&[0x0b, 0xb8]);
+        data[10] = 31;
+        data[14] = 4;
+        data[23..27].copy_from_slice(&[1, 0, 5, 6]);
+
+        assert_eq!(Status::decode(&data).unwrap(), Status {
+            liquid_temperature: 31.4,
+            fan_rpm: 1200,
+            pump_rpm: 3000,
+            firmware_version: "1.0.5.6".to_string(),
+        });
+
+        match Status::decode(&data[..20]) {
+            Err(Error::Decode(DecodeError::TruncatedPacket { expected: STATUS_SIZE, actual: 20 })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
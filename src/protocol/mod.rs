@@ -1 +1,3 @@
 pub mod usbhid;
+pub mod pmbus;
+pub mod asetek;
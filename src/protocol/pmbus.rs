@@ -0,0 +1,419 @@
+//! # PMBus over USB HID
+//!
+//! Corsair's digital power supplies (the HXi, RMi and AXi families) are
+//! monitored through a USB dongle that tunnels PMBus commands over HID. Every
+//! request is a single 64-byte report holding a read or write flag, a command
+//! code and, for writes, the data to write:
+//!
+//! ```text
+//!    [0x03 0x8b 0x00 ...]
+//!      |    \------------ Command 0x8b: READ_VOUT
+//!      \----------------- Flag 0x03: Read
+//!
+//!    [0x02 0x00 0x01 ...]
+//!      |    |    \------- Data: rail 1
+//!      |    \------------ Command 0x00: PAGE
+//!      \----------------- Flag 0x02: Write
+//! ```
+//!
+//! The response is another 64-byte report that echoes the flag and command,
+//! followed by the value read. Before anything else, the dongle must be sent
+//! the two bytes `0xfe 0x03`.
+//!
+//! Measurements use the PMBus LINEAR11 format, an 11-bit signed mantissa and
+//! a 5-bit signed exponent packed into a little-endian word. Corsair's PSUs
+//! report `READ_VOUT` in LINEAR11 too, rather than the LINEAR16 format the
+//! PMBus specification gives it, but both are provided for models that
+//! follow the standard.
+//!
+//! Commands that have one value per output rail, such as `READ_VOUT`, refer
+//! to whichever rail was last selected by writing its number to `PAGE`.
+
+use std::fmt;
+use hex_slice::AsHex;
+use log::Level;
+
+use errors::*;
+use backends::{Backend, WIRE_LOG_TARGET};
+
+pub const REPORT_SIZE: usize = 64;
+
+/// Sent once before any other request to wake the dongle up.
+pub const INIT: [u8; 2] = [0xfe, 0x03];
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Flag {
+    Write = 0x02,
+    Read = 0x03,
+}
+
+/// How a command's value is encoded.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Byte,
+    Linear11,
+    /// A null-terminated ASCII string.
+    String,
+    /// A little-endian count of seconds.
+    Seconds,
+}
+
+/// The standard PMBus commands and Corsair's manufacturer-specific ones that
+/// are shared across its PSUs. Not every model supports every command.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    Page = 0x00,
+    VoutMode = 0x20,
+    /// The fan duty cycle as a whole percentage, used when `FanControlMode`
+    /// is set to fixed. Corsair's PSUs take a single byte here rather than
+    /// the LINEAR11 value the specification gives.
+    FanCommand1 = 0x3b,
+    /// The over-current limit of the selected rail, in amps.
+    IoutOcFaultLimit = 0x46,
+    ReadVin = 0x88,
+    ReadVout = 0x8b,
+    ReadIout = 0x8c,
+    ReadTemperature1 = 0x8d,
+    ReadTemperature2 = 0x8e,
+    ReadFanSpeed1 = 0x90,
+    ReadPout = 0x96,
+    MfrModel = 0x9a,
+    MfrSerial = 0x9e,
+    /// Time the PSU has been on over its whole life.
+    ReadTotalUptime = 0xd1,
+    /// Time the PSU has been on since it was last powered up.
+    ReadUptime = 0xd2,
+    /// Whether over-current protection is per rail or for the 12V output as
+    /// a whole.
+    OcpMode = 0xd8,
+    /// The input power, as estimated by the PSU.
+    ReadInputPower = 0xee,
+    /// Whether the fan is driven by the firmware or at a fixed duty cycle.
+    FanControlMode = 0xf0,
+}
+
+impl Command {
+    pub fn from_u8(command: u8) -> Option<Command> {
+        Some(match command {
+            0x00 => Command::Page,
+            0x20 => Command::VoutMode,
+            0x3b => Command::FanCommand1,
+            0x46 => Command::IoutOcFaultLimit,
+            0x88 => Command::ReadVin,
+            0x8b => Command::ReadVout,
+            0x8c => Command::ReadIout,
+            0x8d => Command::ReadTemperature1,
+            0x8e => Command::ReadTemperature2,
+            0x90 => Command::ReadFanSpeed1,
+            0x96 => Command::ReadPout,
+            0x9a => Command::MfrModel,
+            0x9e => Command::MfrSerial,
+            0xd1 => Command::ReadTotalUptime,
+            0xd2 => Command::ReadUptime,
+            0xd8 => Command::OcpMode,
+            0xee => Command::ReadInputPower,
+            0xf0 => Command::FanControlMode,
+            _ => return None,
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        match *self {
+            Command::Page |
+            Command::VoutMode |
+            Command::FanCommand1 |
+            Command::OcpMode |
+            Command::FanControlMode => Format::Byte,
+            Command::MfrModel |
+            Command::MfrSerial => Format::String,
+            Command::ReadTotalUptime |
+            Command::ReadUptime => Format::Seconds,
+            _ => Format::Linear11,
+        }
+    }
+}
+
+impl From<Command> for u8 {
+    fn from(command: Command) -> u8 { command as u8 }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Linear11(f64),
+    String(String),
+    Seconds(u32),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Byte(byte) => write!(f, "0x{:02x}", byte),
+            Value::Linear11(value) => write!(f, "{}", value),
+            Value::String(ref s) => write!(f, "{:?}", s),
+            Value::Seconds(seconds) => write!(f, "{}s", seconds),
+        }
+    }
+}
+
+/// Decodes a LINEAR11 value.
+pub fn decode_linear11(data: [u8; 2]) -> f64 {
+    let word = u16::from(data[0]) | u16::from(data[1]) << 8;
+    let exponent = (word as i16) >> 11;
+    let mantissa = ((word << 5) as i16) >> 5;
+    f64::from(mantissa) * 2f64.powi(i32::from(exponent))
+}
+
+/// Encodes a value in the LINEAR11 format with as much precision as the
+/// mantissa allows.
+pub fn encode_linear11(value: f64) -> Result<[u8; 2]> {
+    for exponent in -16i32..16 {
+        let mantissa = (value / 2f64.powi(exponent)).round();
+        if (-1024.0..=1023.0).contains(&mantissa) {
+            let word = ((exponent as u16) & 0x1f) << 11 | (mantissa as i16 as u16) & 0x7ff;
+            return Ok([word as u8, (word >> 8) as u8]);
+        }
+    }
+    Err(Error::invalid_argument("value", format!("{} can't be encoded in LINEAR11", value)))
+}
+
+/// Returns the exponent held in the low five bits of a `VOUT_MODE` value,
+/// which LINEAR16 values share.
+fn vout_mode_exponent(vout_mode: u8) -> i32 {
+    i32::from(((vout_mode << 3) as i8) >> 3)
+}
+
+/// Decodes a LINEAR16 value: an unsigned 16-bit mantissa, scaled by the
+/// exponent given by the `VOUT_MODE` command.
+pub fn decode_linear16(data: [u8; 2], vout_mode: u8) -> f64 {
+    let mantissa = u16::from(data[0]) | u16::from(data[1]) << 8;
+    f64::from(mantissa) * 2f64.powi(vout_mode_exponent(vout_mode))
+}
+
+pub fn encode_linear16(value: f64, vout_mode: u8) -> Result<[u8; 2]> {
+    let mantissa = (value / 2f64.powi(vout_mode_exponent(vout_mode))).round();
+    if !(0.0..=f64::from(u16::MAX)).contains(&mantissa) {
+        return Err(Error::invalid_argument("value", format!("{} can't be encoded in LINEAR16 with VOUT_MODE 0x{:02x}", value, vout_mode)));
+    }
+    let word = mantissa as u16;
+    Ok([word as u8, (word >> 8) as u8])
+}
+
+impl Value {
+    /// Decodes the data following the echoed flag and command in a response.
+    pub fn decode(command: Command, data: &[u8]) -> Result<Value> {
+        let expected = match command.format() {
+            Format::Byte => 1,
+            Format::Linear11 => 2,
+            Format::String => 0,
+            Format::Seconds => 4,
+        };
+        if data.len() < expected {
+            return Err(DecodeError::TruncatedValue { register: command.into(), expected, actual: data.len() }.into());
+        }
+
+        match command.format() {
+            Format::Byte => Ok(Value::Byte(data[0])),
+            Format::Linear11 => Ok(Value::Linear11(decode_linear11([data[0], data[1]]))),
+            Format::String => {
+                let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+                match String::from_utf8(data[..end].to_vec()) {
+                    Ok(s) => Ok(Value::String(s)),
+                    Err(error) => Err(DecodeError::InvalidString { register: command.into(), error }.into()),
+                }
+            },
+            Format::Seconds => Ok(Value::Seconds(
+                u32::from(data[0]) | u32::from(data[1]) << 8 | u32::from(data[2]) << 16 | u32::from(data[3]) << 24)),
+        }
+    }
+
+    /// Encodes the value into `buf`, returning the number of bytes used.
+    /// Fails if the value doesn't match the command's format.
+    pub fn encode(&self, command: Command, buf: &mut [u8]) -> Result<usize> {
+        match (command.format(), self) {
+            (Format::Byte, Value::Byte(byte)) => {
+                buf[0] = *byte;
+                Ok(1)
+            },
+            (Format::Linear11, Value::Linear11(value)) => {
+                buf[..2].copy_from_slice(&encode_linear11(*value)?);
+                Ok(2)
+            },
+            (Format::String, Value::String(s)) => {
+                let bytes = s.as_bytes();
+                if bytes.len() >= buf.len() {
+                    return Err(Error::invalid_argument("value", format!("{:?} is too long", s)));
+                }
+                buf[..bytes.len()].copy_from_slice(bytes);
+                buf[bytes.len()] = 0;
+                Ok(bytes.len() + 1)
+            },
+            (Format::Seconds, Value::Seconds(seconds)) => {
+                let seconds = *seconds;
+                buf[..4].copy_from_slice(&[seconds as u8, (seconds >> 8) as u8, (seconds >> 16) as u8, (seconds >> 24) as u8]);
+                Ok(4)
+            },
+            _ => Err(Error::invalid_argument("value", format!("{} doesn't fit the format of command 0x{:02x}", self, u8::from(command)))),
+        }
+    }
+}
+
+/// Writes a request padded to a full report and returns the response.
+pub fn exchange<B: Backend>(backend: &mut B, request: &[u8]) -> Result<[u8; REPORT_SIZE]> {
+    if request.len() > REPORT_SIZE {
+        return Err(Error::invalid_argument("request", format!("{} bytes don't fit in a {}-byte report", request.len(), REPORT_SIZE)));
+    }
+    let mut report = [0u8; REPORT_SIZE];
+    report[..request.len()].copy_from_slice(request);
+    let (read_timeout, write_timeout) = (backend.read_timeout(), backend.write_timeout());
+
+    if log_enabled!(target: WIRE_LOG_TARGET, Level::Trace) {
+        trace!(target: WIRE_LOG_TARGET, direction = "tx"; "{:x}", report.as_hex());
+    }
+    backend.write_report(&report, write_timeout)?;
+
+    let mut response = [0u8; REPORT_SIZE];
+    backend.read_report(&mut response, read_timeout)?;
+    trace!(target: WIRE_LOG_TARGET, direction = "rx"; "{:x}", response.as_hex());
+    Ok(response)
+}
+
+/// Checks that a response echoes the flag and command of its request.
+fn check_echo(request: &[u8], response: &[u8; REPORT_SIZE]) -> Result<()> {
+    if response[..2] != request[..2] {
+        return Err(DecodeError::BadEcho { expected: [request[0], request[1]], actual: [response[0], response[1]] }.into());
+    }
+    Ok(())
+}
+
+pub fn read<B: Backend>(backend: &mut B, command: Command) -> Result<Value> {
+    let request = [Flag::Read as u8, command.into()];
+    let response = exchange(backend, &request)?;
+    check_echo(&request, &response)?;
+    Value::decode(command, &response[2..])
+}
+
+pub fn write<B: Backend>(backend: &mut B, command: Command, value: &Value) -> Result<()> {
+    let mut request = [0u8; REPORT_SIZE];
+    request[0] = Flag::Write as u8;
+    request[1] = command.into();
+    let len = value.encode(command, &mut request[2..])?;
+    let response = exchange(backend, &request[..2 + len])?;
+    check_echo(&request, &response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn round_trip_linear11(value: f64) -> f64 {
+        decode_linear11(encode_linear11(value).unwrap())
+    }
+
+    #[test]
+    fn linear11() {
+        assert_eq!(decode_linear11([0x00, 0x00]), 0.0);
+        // Mantissa 1, exponent -1
+        assert_eq!(decode_linear11([0x01, 0xf8]), 0.5);
+        // Mantissa -1, exponent 0
+        assert_eq!(decode_linear11([0xff, 0x07]), -1.0);
+        // Mantissa -1024, exponent 15
+        assert_eq!(decode_linear11([0x00, 0x7c]), -1024.0 * 32768.0);
+
+        assert_eq!(encode_linear11(12.0).unwrap(), [0x00, 0xd3]);
+        for &value in &[0.0, 12.0, -12.0, 0.5, -0.25, 2.0f64.powi(-16), 1023.0 * 32768.0, -1024.0 * 32768.0] {
+            assert_eq!(round_trip_linear11(value), value);
+        }
+        for &value in &[3.3, -3.3, 230.7, 0.013] {
+            assert!((round_trip_linear11(value) - value).abs() <= value.abs() / 1024.0);
+        }
+    }
+
+    #[test]
+    fn linear11_out_of_range() {
+        for &value in &[1024.0 * 32768.0, -1025.0 * 32768.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(encode_linear11(value), Err(Error::InvalidArgument { .. })));
+        }
+    }
+
+    #[test]
+    fn linear16() {
+        // Exponent -9
+        let vout_mode = 0x17;
+        assert_eq!(encode_linear16(12.0, vout_mode).unwrap(), [0x00, 0x18]);
+        assert_eq!(decode_linear16([0x00, 0x18], vout_mode), 12.0);
+        assert_eq!(decode_linear16([0x00, 0x00], vout_mode), 0.0);
+        assert_eq!(encode_linear16(0.0, vout_mode).unwrap(), [0x00, 0x00]);
+        assert_eq!(decode_linear16([0xff, 0xff], vout_mode), 65535.0 / 512.0);
+
+        // Exponent 1
+        assert_eq!(encode_linear16(6.0, 0x01).unwrap(), [0x03, 0x00]);
+        assert_eq!(decode_linear16([0x03, 0x00], 0x01), 6.0);
+    }
+
+    #[test]
+    fn linear16_out_of_range() {
+        for &value in &[-1.0, 128.0, f64::NAN] {
+            assert!(matches!(encode_linear16(value, 0x17), Err(Error::InvalidArgument { .. })));
+        }
+    }
+
+    #[test]
+    fn encode_rejects_value_of_another_format() {
+        let mut buf = [0u8; REPORT_SIZE];
+        assert!(matches!(Value::Byte(1).encode(Command::ReadVout, &mut buf), Err(Error::InvalidArgument { .. })));
+    }
+
+    /// A backend that answers every request with the same response.
+    struct Canned([u8; REPORT_SIZE]);
+
+    impl Backend for Canned {
+        fn write_report(&mut self, data: &[u8], _timeout: Duration) -> Result<usize> {
+            Ok(data.len())
+        }
+
+        fn read_report(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+            buf.copy_from_slice(&self.0);
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn read_checks_echo() {
+        let mut response = [0u8; REPORT_SIZE];
+        response[..4].copy_from_slice(&[Flag::Read as u8, Command::ReadVout as u8, 0x00, 0xd3]);
+        assert_eq!(read(&mut Canned(response), Command::ReadVout).unwrap(), Value::Linear11(12.0));
+
+        match read(&mut Canned(response), Command::ReadIout) {
+            Err(Error::Decode(DecodeError::BadEcho { expected: [0x03, 0x8c], actual: [0x03, 0x8b] })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_checks_echo() {
+        let mut response = [0u8; REPORT_SIZE];
+        response[..2].copy_from_slice(&[Flag::Write as u8, Command::Page as u8]);
+        write(&mut Canned(response), Command::Page, &Value::Byte(1)).unwrap();
+
+        match write(&mut Canned(response), Command::FanCommand1, &Value::Byte(50)) {
+            Err(Error::Decode(DecodeError::BadEcho { expected: [0x02, 0x3b], actual: [0x02, 0x00] })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        response[0] = Flag::Read as u8;
+        match write(&mut Canned(response), Command::Page, &Value::Byte(1)) {
+            Err(Error::Decode(DecodeError::BadEcho { expected: [0x02, 0x00], actual: [0x03, 0x00] })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn exchange_rejects_oversized_request() {
+        let request = [0u8; REPORT_SIZE + 1];
+        assert!(matches!(exchange(&mut Canned([0; REPORT_SIZE]), &request), Err(Error::InvalidArgument { .. })));
+    }
+}
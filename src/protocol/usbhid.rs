@@ -9,7 +9,9 @@
 //!
 //! Transmitted packets are structured as follows:
 //!
+//! ```text
 //!     LEN <CommandID> <Command> <Command..?> <Zero Padding>
+//! ```
 //!
 //! The first byte is the total length in bytes of the command data contained
 //! in the packet (not including the first len byte). The packet is then
@@ -25,6 +27,7 @@
 //! operating on a block register, a LEN byte is the first byte of the command
 //! data. For example:
 //!
+//! ```text
 //!    [0x07 0x00]
 //!      |    \----- Register 0x00: Device ID
 //!      \---------- Opcode 0x06: ReadByte
@@ -44,13 +47,26 @@
 //!      |    |    \---------- Len 0x08: 8 byte block to read
 //!      |    \--------------- Register 0x02: ProductName
 //!      \-------------------- Opcode 0x0b: ReadBlock
+//! ```
+//!
+//! The response packet echoes each command's ID and opcode, followed by the
+//! data for reads. Block reads are again prefixed by a LEN byte:
+//!
+//! ```text
+//!     <CommandID> <Opcode> [LEN] <Data..?> <CommandID> <Opcode> ... <Zero Padding>
+//! ```
+//!
+//! Responses are decoded without trusting their contents: a short packet, a
+//! command ID or opcode that doesn't match the request, or a block LEN byte
+//! that doesn't match the register all produce errors rather than panics.
 //!
 
+use std::fmt;
 use errors::*;
 
 #[repr(u8)]
-#[derive(Copy, Clone, Debug)]
-enum Opcode {
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Opcode {
     WriteByte = 0x06,
     ReadByte = 0x07,
     WriteWord = 0x08,
@@ -59,13 +75,25 @@ enum Opcode {
     ReadBlock = 0x0b,
 }
 
-pub trait Register : Into<u8> + Copy {
-    fn size(&self) -> usize;
+impl Opcode {
+    pub fn from_u8(data: u8) -> Option<Opcode> {
+        match data {
+            0x06 => Some(Opcode::WriteByte),
+            0x07 => Some(Opcode::ReadByte),
+            0x08 => Some(Opcode::WriteWord),
+            0x09 => Some(Opcode::ReadWord),
+            0x0a => Some(Opcode::WriteBlock),
+            0x0b => Some(Opcode::ReadBlock),
+            _ => None,
+        }
+    }
 }
 
-pub trait Value<R: Register> : Sized + Clone {
-    type DecodeError;
+pub trait Register : Into<u8> + Copy + fmt::Debug {
+    fn size(&self) -> usize;
+}
 
+pub trait Value<R: Register> : Sized + Clone + fmt::Debug {
     fn decode(register: R, data: &[u8]) -> Result<Self>;
 
     fn encode(&self, buf: &mut [u8]) -> Option<usize>;
@@ -77,6 +105,15 @@ pub enum Command<R,V> {
     Write(R,V),
 }
 
+impl<R: fmt::Debug, V> fmt::Display for Command<R,V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::Read(ref register) => write!(f, "Read({:?})", register),
+            Command::Write(ref register, _) => write!(f, "Write({:?})", register),
+        }
+    }
+}
+
 impl<R: Register, V: Value<R>> Command<R,V> {
     fn opcode(&self) -> Opcode {
         match self {
@@ -119,16 +156,16 @@ impl<R: Register, V: Value<R>> Command<R,V> {
             &Command::Write(register, ref value) => {
                 match self.opcode() {
                     Opcode::WriteByte => {
-                        value.encode(&mut buf[2..3]);
+                        value.encode(&mut buf[2..3])?;
                         Some(3)
                     },
                     Opcode::WriteWord => {
-                        value.encode(&mut buf[2..4]);
+                        value.encode(&mut buf[2..4])?;
                         Some(4)
                     },
                     Opcode::WriteBlock => {
                         buf[2] = register.size() as u8;
-                        value.encode(&mut buf[3..3+register.size()]);
+                        value.encode(&mut buf[3..3+register.size()])?;
                         Some(2 + register.size())
                     },
                     _ => None
@@ -147,11 +184,75 @@ impl<R: Register, V: Value<R>> Command<R,V> {
             Opcode::WriteBlock => 3 + self.register().size(),
         }
     }
+
+    /// The number of bytes the response to this command occupies in an
+    /// `RxPacket`, not including its command ID.
+    fn response_len(&self) -> usize {
+        match self.opcode() {
+            Opcode::ReadByte => 2,
+            Opcode::ReadWord => 3,
+            Opcode::ReadBlock => 2 + self.register().size(),
+            Opcode::WriteByte | Opcode::WriteWord | Opcode::WriteBlock => 1,
+        }
+    }
 }
 
 pub const PACKET_SIZE: usize = 64;
 pub const FIRST_COMMAND_ID: u8 = 20;
 
+/// Returns the command ID that follows a packet of `count` commands starting
+/// at `command_id`, wrapping back around to `FIRST_COMMAND_ID` rather than
+/// overflowing.
+pub fn next_command_id(command_id: u8, count: usize) -> u8 {
+    if command_id as usize + count > 255 {
+        FIRST_COMMAND_ID
+    } else {
+        command_id + count as u8
+    }
+}
+
+/// Splits a batch of commands into as many packets as are needed for both the
+/// request and its response to fit in `PACKET_SIZE` bytes.
+///
+/// Command IDs are assigned consecutively across the packets, starting at
+/// `command_id`, which is updated to the ID that follows the last packet. A
+/// packet never spans the wrap from 255 back to `FIRST_COMMAND_ID`.
+pub fn packetize<R: Register, V: Value<R>>(command_id: &mut u8, commands: Vec<Command<R,V>>) -> Result<Vec<TxPacket<R,V>>> {
+    let mut packets = Vec::new();
+    let mut current: Vec<Command<R,V>> = Vec::new();
+    let (mut tx_len, mut rx_len) = (1, 0);
+
+    for command in commands {
+        let (command_tx_len, command_rx_len) = (command.len() + 1, command.response_len() + 1);
+        if 1 + command_tx_len > PACKET_SIZE || command_rx_len > PACKET_SIZE {
+            return Err(ProtocolError::PacketTooLarge { len: 1 + command_tx_len.max(command_rx_len) }.into());
+        }
+
+        let full = tx_len + command_tx_len > PACKET_SIZE || rx_len + command_rx_len > PACKET_SIZE;
+        let out_of_ids = *command_id as usize + current.len() + 1 > 256;
+        if full || out_of_ids {
+            let count = current.len();
+            packets.push(TxPacket::new(*command_id, current));
+            *command_id = next_command_id(*command_id, count);
+            current = Vec::new();
+            tx_len = 1;
+            rx_len = 0;
+        }
+
+        tx_len += command_tx_len;
+        rx_len += command_rx_len;
+        current.push(command);
+    }
+
+    if !current.is_empty() {
+        let count = current.len();
+        packets.push(TxPacket::new(*command_id, current));
+        *command_id = next_command_id(*command_id, count);
+    }
+
+    Ok(packets)
+}
+
 #[derive(Debug)]
 pub struct TxPacket<R,V> {
     first_command_id: u8,
@@ -163,8 +264,22 @@ impl<R: Register, V: Value<R>> TxPacket<R,V> {
         TxPacket { first_command_id, commands }
     }
 
-    pub fn encode(self: &TxPacket<R,V>) -> Option<Vec<u8>> {
+    pub fn first_command_id(&self) -> u8 {
+        self.first_command_id
+    }
+
+    pub fn commands(&self) -> &[Command<R,V>] {
+        &self.commands[..]
+    }
+
+    /// Encodes the packet, failing if it would not fit in `PACKET_SIZE` bytes
+    /// or if any of its values can't be written.
+    pub fn encode(self: &TxPacket<R,V>) -> Result<Vec<u8>> {
         let len = self.len();
+        if len > PACKET_SIZE {
+            return Err(ProtocolError::PacketTooLarge { len }.into());
+        }
+
         let mut buf: Vec<u8> = vec![0; len];
         buf[0] = len as u8 - 1;
 
@@ -174,15 +289,14 @@ impl<R: Register, V: Value<R>> TxPacket<R,V> {
         for c in self.commands.iter() {
             buf[i] = command_id;
             i += 1;
-            match buf.get_mut(i .. i + c.len()) {
-                Some(slice) => c.encode(slice),
-                None => return None
-            };
+            if buf.get_mut(i .. i + c.len()).and_then(|slice| c.encode(slice)).is_none() {
+                return Err(Error::UnsupportedRegister(c.register().into()));
+            }
             i += c.len();
-            command_id += 1;
+            command_id = command_id.wrapping_add(1);
         }
 
-        Some(buf)
+        Ok(buf)
     }
 
     pub fn len(&self) -> usize {
@@ -198,23 +312,18 @@ pub enum RxCommand<R, V> {
 
 impl<R: Register, V: Value<R>> RxCommand<R, V> {
     fn decode_read(register: R, data: &[u8]) -> Result<RxCommand<R, V>> {
-        let buf = match register.size() {
-            1 => &data[0..1],
-            2 => &data[0..2],
-            len @ _ if len == data[0] as usize => &data[1..len+2],
-            _ => return Err("Invalid length byte for block read".into()),
-        };
-        Ok(RxCommand::Read(register, V::decode(register, buf)?))
-    }
-
-    fn len(&self) -> usize {
-        match self {
-            &RxCommand::Read(register, _) => match register.size() {
-                1 => 2,
-                2 => 3,
-                len @ _ => len + 2,
+        let size = register.size();
+        let buf = match size {
+            1 | 2 => data.get(0..size),
+            _ => match data.first() {
+                Some(&len) if len as usize == size => data.get(1..size + 1),
+                Some(&len) => return Err(DecodeError::BadBlockLength { register: register.into(), expected: size as u8, actual: len }.into()),
+                None => None,
             },
-            &RxCommand::Write(_) => 1,
+        };
+        match buf {
+            Some(buf) => Ok(RxCommand::Read(register, V::decode(register, buf)?)),
+            None => Err(DecodeError::TruncatedValue { register: register.into(), expected: size, actual: data.len() }.into()),
         }
     }
 }
@@ -222,31 +331,54 @@ impl<R: Register, V: Value<R>> RxCommand<R, V> {
 #[derive(Debug)]
 pub struct RxPacket<R,V>(Vec<RxCommand<R,V>>);
 
+impl<R: Register, V: Value<R>> Default for RxPacket<R,V> {
+    fn default() -> RxPacket<R,V> {
+        RxPacket::new()
+    }
+}
+
 impl<R: Register, V: Value<R>> RxPacket<R,V> {
+    pub fn new() -> RxPacket<R,V> {
+        RxPacket(Vec::new())
+    }
+
     pub fn decode(tx_packet: TxPacket<R,V>, data: &[u8]) -> Result<RxPacket<R,V>> {
-        let mut rxpacket = RxPacket(Vec::new());
+        let mut rxpacket = RxPacket::new();
 
         let mut command_id = tx_packet.first_command_id;
         let mut i = 0;
         for c in tx_packet.commands.iter() {
+            let end = i + 1 + c.response_len();
+            if end > data.len() {
+                return Err(DecodeError::TruncatedPacket { expected: end, actual: data.len() }.into());
+            }
+
             if data[i] != command_id {
-                println!("Bad command ID {}", data[i]);
-                return Err("Bad command ID".into());
+                return Err(ProtocolError::BadCommandId { expected: command_id, actual: data[i] }.into());
+            }
+            if data[i + 1] != c.opcode() as u8 {
+                return Err(ProtocolError::BadOpcode { command_id, expected: c.opcode() as u8, actual: data[i + 1] }.into());
             }
 
             let rxcommand = match c {
-                &Command::Read(register) => RxCommand::decode_read(register, &data[i + 2 .. ])?,
+                &Command::Read(register) => RxCommand::decode_read(register, &data[i + 2 .. end])?,
                 &Command::Write(register, _) => RxCommand::Write(register),
             };
 
-            command_id += 1;
-            i += rxcommand.len() + 1;
+            command_id = command_id.wrapping_add(1);
+            i = end;
             rxpacket.0.push(rxcommand);
         }
 
         Ok(rxpacket)
     }
 
+    /// Appends the commands of a response to a later packet in the same
+    /// batch.
+    pub fn append(&mut self, other: RxPacket<R,V>) {
+        self.0.extend(other.0);
+    }
+
     pub fn read_values(&self) -> Vec<V> {
         self.0.iter().filter_map(|rxcommand| {
             match rxcommand {
@@ -256,3 +388,134 @@ impl<R: Register, V: Value<R>> RxPacket<R,V> {
         }).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devices::cooler::h110i::{Register as R, RegisterValue as V, RgbColor};
+
+    fn packet_sizes(packets: &[TxPacket<R, V>]) -> Vec<(u8, usize)> {
+        packets.iter().map(|p| (p.first_command_id(), p.commands().len())).collect()
+    }
+
+    /// Three 16-byte block writes and three 5-byte word writes, which with the
+    /// length byte fill a request exactly.
+    fn full_request() -> Vec<Command<R, V>> {
+        let mut commands = Vec::new();
+        for _ in 0..3 {
+            commands.push(Command::Write(R::LedCycleColors, V::LedCycleColors([RgbColor(1, 2, 3); 4])));
+        }
+        for _ in 0..3 {
+            commands.push(Command::Write(R::FanFixedRPM, V::FanFixedRPM(1000)));
+        }
+        commands
+    }
+
+    /// Five 11-byte block reads and three 3-byte byte reads, whose responses
+    /// fill a report exactly.
+    fn full_response() -> Vec<Command<R, V>> {
+        let mut commands = Vec::new();
+        for _ in 0..5 {
+            commands.push(Command::Read(R::ProductName));
+        }
+        for _ in 0..3 {
+            commands.push(Command::Read(R::DeviceId));
+        }
+        commands
+    }
+
+    #[test]
+    fn request_that_fills_a_packet_is_not_split() {
+        let mut command_id = FIRST_COMMAND_ID;
+        let packets = packetize(&mut command_id, full_request()).unwrap();
+        assert_eq!(packet_sizes(&packets), vec![(20, 6)]);
+        assert_eq!(packets[0].encode().unwrap().len(), PACKET_SIZE);
+        assert_eq!(command_id, 26);
+    }
+
+    #[test]
+    fn request_one_byte_over_a_packet_is_split() {
+        let mut commands = full_request();
+        commands.push(Command::Write(R::FanSelect, V::FanSelect(0)));
+
+        let mut command_id = FIRST_COMMAND_ID;
+        let packets = packetize(&mut command_id, commands).unwrap();
+        assert_eq!(packet_sizes(&packets), vec![(20, 6), (26, 1)]);
+        assert_eq!(command_id, 27);
+    }
+
+    #[test]
+    fn response_that_fills_a_packet_is_not_split() {
+        let mut command_id = FIRST_COMMAND_ID;
+        assert_eq!(packet_sizes(&packetize(&mut command_id, full_response()).unwrap()), vec![(20, 8)]);
+
+        let mut commands = full_response();
+        commands.push(Command::Read(R::DeviceId));
+        let mut command_id = FIRST_COMMAND_ID;
+        assert_eq!(packet_sizes(&packetize(&mut command_id, commands).unwrap()), vec![(20, 8), (28, 1)]);
+    }
+
+    #[test]
+    fn command_ids_wrap_between_packets() {
+        let commands = (0..10).map(|_| Command::Read(R::DeviceId)).collect();
+        let mut command_id = 250;
+        let packets = packetize(&mut command_id, commands).unwrap();
+        assert_eq!(packet_sizes(&packets), vec![(250, 6), (FIRST_COMMAND_ID, 4)]);
+        assert_eq!(command_id, FIRST_COMMAND_ID + 4);
+    }
+
+    #[test]
+    fn batch_ending_at_255_wraps_the_next_id() {
+        let commands = (0..6).map(|_| Command::Read(R::DeviceId)).collect();
+        let mut command_id = 250;
+        let packets = packetize(&mut command_id, commands).unwrap();
+        assert_eq!(packet_sizes(&packets), vec![(250, 6)]);
+        assert_eq!(packets[0].encode().unwrap()[16], 255);
+        assert_eq!(command_id, FIRST_COMMAND_ID);
+    }
+
+    fn decode(commands: Vec<Command<R, V>>, response: &[u8]) -> Result<RxPacket<R, V>> {
+        RxPacket::decode(TxPacket::new(FIRST_COMMAND_ID, commands), response)
+    }
+
+    #[test]
+    fn decodes_response() {
+        let rx = decode(vec![Command::Write(R::FanSelect, V::FanSelect(1)), Command::Read(R::FanRPM)],
+                        &[20, 0x06, 21, 0x09, 0x4c, 0x04]).unwrap();
+        assert_eq!(rx.read_values(), vec![V::FanRPM(1100)]);
+    }
+
+    #[test]
+    fn truncated_packet() {
+        match decode(vec![Command::Read(R::FanRPM)], &[20, 0x09, 0x4c]) {
+            Err(Error::Decode(DecodeError::TruncatedPacket { expected: 4, actual: 3 })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mismatched_command_id() {
+        match decode(vec![Command::Read(R::DeviceId)], &[21, 0x07, 0x42]) {
+            Err(Error::Protocol(ProtocolError::BadCommandId { expected: 20, actual: 21 })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        match decode(vec![Command::Read(R::DeviceId)], &[20, 0x42, 0x42]) {
+            Err(Error::Protocol(ProtocolError::BadOpcode { command_id: 20, expected: 0x07, actual: 0x42 })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bad_block_length() {
+        let mut response = vec![20, 0x0b, 0x07];
+        response.extend_from_slice(b"\x05H110i\x00\x00");
+        match decode(vec![Command::Read(R::ProductName)], &response) {
+            Err(Error::Decode(DecodeError::BadBlockLength { register: 0x02, expected: 8, actual: 7 })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
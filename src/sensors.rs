@@ -0,0 +1,247 @@
+//! # Host temperature sensors
+//!
+//! Sources of temperatures measured by the host rather than the device, such
+//! as the CPU package temperature. These can be pushed into a cooler so that
+//! its firmware fan curves and LEDs follow the CPU instead of the coolant.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use errors::*;
+
+/// The sysfs directory holding one `hwmonN` directory per hardware monitor.
+pub const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+/// Something that can be asked for a temperature.
+pub trait TemperatureSource {
+    /// Returns the current temperature in degrees Celsius.
+    fn read_degrees_c(&mut self) -> Result<f64>;
+}
+
+impl<S: TemperatureSource + ?Sized> TemperatureSource for Box<S> {
+    fn read_degrees_c(&mut self) -> Result<f64> {
+        (**self).read_degrees_c()
+    }
+}
+
+/// A Linux hwmon temperature input, such as
+/// `/sys/class/hwmon/hwmon2/temp1_input`, which reports millidegrees Celsius.
+#[derive(Clone, Debug)]
+pub struct Hwmon {
+    path: PathBuf,
+}
+
+fn read_trimmed(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+impl Hwmon {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Hwmon {
+        Hwmon { path: path.into() }
+    }
+
+    /// Finds a temperature input by the name of its chip, such as `coretemp`
+    /// or `k10temp`, and optionally its label, such as `Package id 0` or
+    /// `Tctl`. Without a label the chip's first input is used.
+    ///
+    /// `root` is normally `HWMON_ROOT`.
+    pub fn find<P: AsRef<Path>>(root: P, chip: &str, label: Option<&str>) -> Result<Hwmon> {
+        let mut chips: Vec<PathBuf> = fs::read_dir(root.as_ref())
+            .map_err(|e| Error::Sensor(format!("{}: {}", root.as_ref().display(), e)))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        chips.sort();
+
+        for dir in chips {
+            if read_trimmed(&dir.join("name")).ok().as_ref().map(|s| &s[..]) != Some(chip) {
+                continue;
+            }
+
+            let mut inputs: Vec<(u32, PathBuf)> = match fs::read_dir(&dir) {
+                Ok(entries) => entries.filter_map(|entry| {
+                    let entry = entry.ok()?;
+                    let name = entry.file_name().into_string().ok()?;
+                    if !name.starts_with("temp") || !name.ends_with("_input") {
+                        return None;
+                    }
+                    let index = name["temp".len()..name.len() - "_input".len()].parse().ok()?;
+                    Some((index, entry.path()))
+                }).collect(),
+                Err(_) => continue,
+            };
+            inputs.sort();
+
+            for (index, path) in inputs {
+                match label {
+                    None => return Ok(Hwmon::new(path)),
+                    Some(label) => {
+                        let found = read_trimmed(&dir.join(format!("temp{}_label", index)));
+                        if found.ok().as_ref().map(|s| &s[..]) == Some(label) {
+                            return Ok(Hwmon::new(path));
+                        }
+                    },
+                }
+            }
+        }
+
+        Err(Error::Sensor(match label {
+            Some(label) => format!("no hwmon temperature labelled {:?} on chip {:?}", label, chip),
+            None => format!("no hwmon temperature on chip {:?}", chip),
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl TemperatureSource for Hwmon {
+    fn read_degrees_c(&mut self) -> Result<f64> {
+        let value = read_trimmed(&self.path).map_err(|e| Error::Sensor(format!("{}: {}", self.path.display(), e)))?;
+        match value.parse::<i64>() {
+            Ok(millidegrees) => Ok(millidegrees as f64 / 1000.0),
+            Err(_) => Err(Error::Sensor(format!("{}: invalid temperature {:?}", self.path.display(), value))),
+        }
+    }
+}
+
+/// Runs a shell command and reads a temperature in degrees Celsius from the
+/// start of its output, e.g. `nvidia-smi --query-gpu=temperature.gpu
+/// --format=csv,noheader`.
+#[derive(Clone, Debug)]
+pub struct CommandOutput {
+    command: String,
+}
+
+impl CommandOutput {
+    pub fn new<S: Into<String>>(command: S) -> CommandOutput {
+        CommandOutput { command: command.into() }
+    }
+}
+
+impl TemperatureSource for CommandOutput {
+    fn read_degrees_c(&mut self) -> Result<f64> {
+        let output = process::Command::new("sh").arg("-c").arg(&self.command).output()
+            .map_err(|e| Error::Sensor(format!("{}: {}", self.command, e)))?;
+        if !output.status.success() {
+            return Err(Error::Sensor(format!("{}: {}", self.command, output.status)));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout.split_whitespace().next().map(|word| word.parse::<f64>()) {
+            Some(Ok(degrees)) => Ok(degrees),
+            _ => Err(Error::Sensor(format!("{}: no temperature in output {:?}", self.command, stdout.trim()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::Duration;
+    use backends::simulator::Simulator;
+    use devices::cooler::h110i::{Device, Temperature};
+
+    /// A fake `/sys/class/hwmon`, removed when dropped.
+    struct FakeHwmon {
+        root: PathBuf,
+    }
+
+    impl FakeHwmon {
+        fn new(name: &str) -> FakeHwmon {
+            let root = env::temp_dir().join(format!("corsairlink-hwmon-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            FakeHwmon { root }
+        }
+
+        fn chip(&self, dir: &str, name: &str, inputs: &[(u32, Option<&str>, &str)]) -> &FakeHwmon {
+            let dir = self.root.join(dir);
+            fs::create_dir(&dir).unwrap();
+            fs::write(dir.join("name"), format!("{}\n", name)).unwrap();
+            for &(index, label, value) in inputs {
+                fs::write(dir.join(format!("temp{}_input", index)), format!("{}\n", value)).unwrap();
+                if let Some(label) = label {
+                    fs::write(dir.join(format!("temp{}_label", index)), format!("{}\n", label)).unwrap();
+                }
+            }
+            self
+        }
+    }
+
+    impl Drop for FakeHwmon {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn fake_hwmon(name: &str) -> FakeHwmon {
+        let hwmon = FakeHwmon::new(name);
+        hwmon
+            .chip("hwmon0", "acpitz", &[(1, None, "27800")])
+            .chip("hwmon1", "coretemp", &[(1, Some("Package id 0"), "45000"), (2, Some("Core 0"), "43000"), (10, Some("Core 8"), "41000")])
+            .chip("hwmon2", "nvme", &[(1, Some("Composite"), "38850")]);
+        hwmon
+    }
+
+    #[test]
+    fn finds_input_by_chip_and_label() {
+        let hwmon = fake_hwmon("label");
+        let mut sensor = Hwmon::find(&hwmon.root, "coretemp", Some("Core 8")).unwrap();
+        assert_eq!(sensor.path(), hwmon.root.join("hwmon1/temp10_input"));
+        assert_eq!(sensor.read_degrees_c().unwrap(), 41.0);
+    }
+
+    #[test]
+    fn finds_first_input_without_label() {
+        let hwmon = fake_hwmon("first");
+        let mut sensor = Hwmon::find(&hwmon.root, "coretemp", None).unwrap();
+        assert_eq!(sensor.path(), hwmon.root.join("hwmon1/temp1_input"));
+        assert_eq!(sensor.read_degrees_c().unwrap(), 45.0);
+
+        let mut sensor = Hwmon::find(&hwmon.root, "nvme", None).unwrap();
+        assert_eq!(sensor.read_degrees_c().unwrap(), 38.85);
+    }
+
+    #[test]
+    fn missing_chip_or_label() {
+        let hwmon = fake_hwmon("missing");
+        assert!(matches!(Hwmon::find(&hwmon.root, "k10temp", None), Err(Error::Sensor(_))));
+        assert!(matches!(Hwmon::find(&hwmon.root, "coretemp", Some("Tctl")), Err(Error::Sensor(_))));
+        assert!(matches!(Hwmon::find(hwmon.root.join("nonexistent"), "coretemp", None), Err(Error::Sensor(_))));
+    }
+
+    #[test]
+    fn invalid_reading() {
+        let hwmon = FakeHwmon::new("invalid");
+        hwmon.chip("hwmon0", "coretemp", &[(1, None, "hot")]);
+        let mut sensor = Hwmon::find(&hwmon.root, "coretemp", None).unwrap();
+        assert!(matches!(sensor.read_degrees_c(), Err(Error::Sensor(_))));
+    }
+
+    #[test]
+    fn report_temperature() {
+        let mut cooler = Device::new(Simulator::new());
+        cooler.get_metadata().unwrap();
+        cooler.report_temperature(Temperature::from_degrees_c(42.5).unwrap()).unwrap();
+
+        let simulator = cooler.backend();
+        assert!(simulator.fans.iter().all(|fan| fan.ext_temp == 0x2a80));
+        assert!(simulator.leds.iter().all(|led| led.temperature == 0x2a80));
+    }
+
+    #[test]
+    fn follow_hwmon_temperature() {
+        let hwmon = fake_hwmon("follow");
+        let mut sensor = Hwmon::find(&hwmon.root, "coretemp", Some("Package id 0")).unwrap();
+        let mut cooler = Device::new(Simulator::new());
+        cooler.get_metadata().unwrap();
+
+        let mut runs = 0;
+        cooler.follow_temperature(&mut sensor, Duration::from_millis(0), || { runs += 1; runs == 1 }).unwrap();
+        assert!(cooler.backend().fans.iter().all(|fan| fan.ext_temp == 45 * 256));
+    }
+}